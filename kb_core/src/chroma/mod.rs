@@ -1,185 +1,453 @@
-use crate::config;
-use indicatif::ProgressBar;
-use reqwest::Client;
+use crate::config::AppConfig;
+use crate::embedding::EmbeddingInfo;
+use crate::retry::{self, RetryPolicy};
+use crate::store::{MetadataFilter, QueryHit, VectorRecord, VectorStore};
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{Certificate, Client};
 use serde::Serialize;
-use std::path::Path;
+use std::fs;
+use std::sync::Mutex;
 
-pub const TENANT: &str = "default_tenant";
-pub const DATABASE: &str = "default_database";
-pub const COLLECTION: &str = "kb_index";
+const GET_PAGE_SIZE: usize = 1000;
+/// Most file paths sent in a query's `where` clause. Longer path filters are
+/// applied to the hits instead, over a larger set of results.
+const MAX_WHERE_SOURCES: usize = 500;
+const SOURCE_FILTER_OVERFETCH: usize = 4;
 
 #[derive(Serialize)]
-pub struct ChromaV2AddRequest {
+pub struct ChromaV2UpsertRequest {
     documents: Vec<String>,
     ids: Vec<String>,
     embeddings: Vec<Vec<f32>>,
     metadatas: Vec<serde_json::Value>,
 }
 
-#[derive(Serialize)]
-pub struct SearchResult<'a> {
-    pub index: usize,
-    pub source: &'a str,
-    pub distance: f64,
-    pub content: &'a str,
+/// Builds the HTTP client for Chroma requests, with the configured auth
+/// headers and TLS settings applied.
+pub fn http_client(config: &AppConfig) -> anyhow::Result<Client> {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = &config.chroma_auth_token {
+        match config.chroma_auth_header() {
+            "authorization" => {
+                headers.insert(AUTHORIZATION, sensitive(&format!("Bearer {}", token))?);
+            }
+            "x-chroma-token" => {
+                headers.insert("x-chroma-token", sensitive(token)?);
+            }
+            other => anyhow::bail!(
+                "Unknown chroma_auth_header '{}'. Expected one of: authorization, x-chroma-token",
+                other
+            ),
+        }
+    } else if let Some(username) = &config.chroma_username {
+        let credentials = format!("{}:{}", username, config.chroma_password.as_deref().unwrap_or_default());
+        headers.insert(AUTHORIZATION, sensitive(&format!("Basic {}", BASE64_STANDARD.encode(credentials)))?);
+    }
+
+    let mut builder = Client::builder().default_headers(headers);
+    if let Some(path) = &config.chroma_ca_cert {
+        let pem = fs::read(path)
+            .with_context(|| format!("Failed to read Chroma CA certificate {}", path.display()))?;
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+    if config.chroma_accept_invalid_certs.unwrap_or(false) {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder.build()?)
 }
 
-pub async fn get_collection_id(client: &Client) -> anyhow::Result<String> {
-    let config = config::load_config()?;
-    let url = format!(
-        "{}/api/v2/tenants/{}/databases/{}/collections",
-        config.chroma_host, TENANT, DATABASE
-    );
+/// Header value that is kept out of debug output.
+fn sensitive(value: &str) -> anyhow::Result<HeaderValue> {
+    let mut value = HeaderValue::from_str(value).context("Chroma credentials contain invalid characters")?;
+    value.set_sensitive(true);
+    Ok(value)
+}
 
-    let resp = client.get(&url).send().await?;
-    let status = resp.status();
-    let body = resp.text().await?;
+/// Client for one Chroma collection.
+///
+/// Holds the config and HTTP client for the whole run and resolves the
+/// collection on first use, so each request is a single round trip.
+pub struct ChromaClient {
+    client: Client,
+    config: AppConfig,
+    retry: RetryPolicy,
+    collection: String,
+    resolved: Mutex<Option<Collection>>,
+}
 
-    if !status.is_success() {
-        println!("❌ Failed to list collections: HTTP {} - {}", status, body);
-        anyhow::bail!("Failed to fetch collections");
+/// ID and metadata of a collection as returned by Chroma.
+#[derive(Clone)]
+struct Collection {
+    id: String,
+    metadata: serde_json::Value,
+}
+
+impl Collection {
+    fn parse(value: &serde_json::Value) -> Option<Self> {
+        Some(Collection {
+            id: value.get("id")?.as_str()?.to_string(),
+            metadata: value.get("metadata").cloned().unwrap_or_default(),
+        })
     }
+}
+
+impl ChromaClient {
+    pub fn new(config: &AppConfig, collection: &str) -> anyhow::Result<Self> {
+        Ok(ChromaClient {
+            client: http_client(config)?,
+            config: config.clone(),
+            retry: RetryPolicy::from_config(config),
+            collection: collection.to_string(),
+            resolved: Mutex::new(None),
+        })
+    }
+
+    fn collections_url(&self) -> String {
+        format!(
+            "{}/api/v2/tenants/{}/databases/{}/collections",
+            self.config.chroma_host,
+            self.config.chroma_tenant(),
+            self.config.chroma_database()
+        )
+    }
+
+    /// URL of an endpoint of this collection, e.g. `upsert` or `query`.
+    async fn endpoint(&self, name: &str) -> anyhow::Result<String> {
+        Ok(format!("{}/{}/{}", self.collections_url(), self.collection_id().await?, name))
+    }
+
+    /// The collection's ID, looked up once and then cached.
+    pub async fn collection_id(&self) -> anyhow::Result<String> {
+        Ok(self.resolve().await?.id)
+    }
+
+    async fn resolve(&self) -> anyhow::Result<Collection> {
+        if let Some(collection) = self.resolved.lock().unwrap().clone() {
+            return Ok(collection);
+        }
+
+        let collection = self.find_collection().await?;
+        *self.resolved.lock().unwrap() = Some(collection.clone());
+        Ok(collection)
+    }
+
+    async fn find_collection(&self) -> anyhow::Result<Collection> {
+        let resp = retry::send_with_retry(self.client.get(self.collections_url()), &self.retry).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
 
-    let collections: serde_json::Value = serde_json::from_str(&body)?;
-    if let Some(arr) = collections.as_array() {
-        for collection in arr {
-            if collection.get("name") == Some(&serde_json::Value::String(COLLECTION.to_string())) {
-                if let Some(id) = collection.get("id").and_then(|v| v.as_str()) {
-                    return Ok(id.to_string());
+        if !status.is_success() {
+            println!("❌ Failed to list collections: HTTP {} - {}", status, body);
+            anyhow::bail!("Failed to fetch collections");
+        }
+
+        let collections: serde_json::Value = serde_json::from_str(&body)?;
+        if let Some(arr) = collections.as_array() {
+            for collection in arr {
+                if collection.get("name").and_then(|v| v.as_str()) == Some(self.collection.as_str()) {
+                    if let Some(collection) = Collection::parse(collection) {
+                        return Ok(collection);
+                    }
                 }
             }
         }
+
+        anyhow::bail!("Collection '{}' not found", self.collection)
     }
 
-    anyhow::bail!("Collection '{}' not found", COLLECTION)
-}
+    /// Creates the collection unless it exists, recording the embedding model
+    /// in its metadata.
+    pub async fn create_collection_if_missing(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+        let payload = serde_json::json!({
+            "name": self.collection,
+            "metadata": embedding.to_metadata(),
+            "embedding_function": {
+                "type": self.config.embedding_provider(),
+                "model": embedding.model
+            }
+        });
+
+        let resp = retry::send_with_retry(self.client.post(self.collections_url()).json(&payload), &self.retry)
+            .await?;
+
+        match resp.status() {
+            reqwest::StatusCode::CONFLICT => Ok(()),
+            status if status.is_success() => {
+                let created: serde_json::Value = resp.json().await?;
+                if let Some(collection) = Collection::parse(&created) {
+                    *self.resolved.lock().unwrap() = Some(collection);
+                }
+                println!("✅ Created collection '{}'", self.collection);
+                Ok(())
+            }
+            status => {
+                let body = resp.text().await?;
+                println!("❌ Failed to create collection: HTTP {} - {}", status, body);
+                anyhow::bail!("Failed to create collection")
+            }
+        }
+    }
+
+    /// Adds the embedding model to the collection's metadata.
+    pub async fn record_embedding(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+        let mut collection = self.resolve().await?;
+        if !collection.metadata.is_object() {
+            collection.metadata = serde_json::json!({});
+        }
+        if let (Some(metadata), Some(info)) = (collection.metadata.as_object_mut(), embedding.to_metadata().as_object()) {
+            metadata.extend(info.clone());
+        }
 
-pub async fn create_collection_if_missing(client: &Client) -> anyhow::Result<()> {
-    let config = config::load_config()?;
-    let url = format!(
-        "{}/api/v2/tenants/{}/databases/{}/collections",
-        config.chroma_host, TENANT, DATABASE
-    );
+        let url = format!("{}/{}", self.collections_url(), collection.id);
+        let payload = serde_json::json!({ "new_metadata": collection.metadata });
+        let resp = retry::send_with_retry(self.client.put(&url).json(&payload), &self.retry).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
 
-    let payload = serde_json::json!({
-        "name": COLLECTION,
-        "embedding_function": {
-            "type": "openai",
-            "model": "text-embedding-3-large"
+        if !status.is_success() {
+            anyhow::bail!("Failed to update collection '{}': HTTP {} - {}", self.collection, status, body);
         }
-    });
 
-    let resp = client.post(&url).json(&payload).send().await?;
+        *self.resolved.lock().unwrap() = Some(collection);
+        Ok(())
+    }
+
+    pub async fn upsert(&self, records: &[VectorRecord]) -> anyhow::Result<()> {
+        let payload = ChromaV2UpsertRequest {
+            ids: records.iter().map(|r| r.id.clone()).collect(),
+            embeddings: records.iter().map(|r| r.embedding.clone()).collect(),
+            documents: records.iter().map(|r| r.document.clone()).collect(),
+            metadatas: records.iter().map(|r| r.metadata.clone()).collect(),
+        };
 
-    match resp.status() {
-        reqwest::StatusCode::CONFLICT => Ok(()),
-        status if status.is_success() => {
-            println!("✅ Created collection '{}'", COLLECTION);
-            Ok(())
+        let url = self.endpoint("upsert").await?;
+        let resp = retry::send_with_retry(self.client.post(&url).json(&payload), &self.retry).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Failed to upsert into Chroma: HTTP {} - {}", status, body);
         }
-        status => {
+
+        Ok(())
+    }
+
+    /// Fetches the ID and metadata of every chunk in the collection, a page at a time.
+    pub async fn get_chunk_metadata(&self) -> anyhow::Result<Vec<(String, serde_json::Value)>> {
+        let url = self.endpoint("get").await?;
+
+        let mut chunks = Vec::new();
+        loop {
+            let payload = serde_json::json!({
+                "include": ["metadatas"],
+                "limit": GET_PAGE_SIZE,
+                "offset": chunks.len()
+            });
+
+            let resp = retry::send_with_retry(self.client.post(&url).json(&payload), &self.retry).await?;
+            let status = resp.status();
             let body = resp.text().await?;
-            println!("❌ Failed to create collection: HTTP {} - {}", status, body);
-            anyhow::bail!("Failed to create collection")
+
+            if !status.is_success() {
+                anyhow::bail!("Failed to list chunks: HTTP {} - {}", status, body);
+            }
+
+            let parsed: serde_json::Value = serde_json::from_str(&body)?;
+            let ids = parsed["ids"].as_array().cloned().unwrap_or_default();
+            let metadatas = parsed["metadatas"].as_array().cloned().unwrap_or_default();
+            let page = ids.len();
+
+            chunks.extend(
+                ids.into_iter()
+                    .zip(metadatas)
+                    .filter_map(|(id, metadata)| Some((id.as_str()?.to_string(), metadata))),
+            );
+
+            if page < GET_PAGE_SIZE {
+                return Ok(chunks);
+            }
+        }
+    }
+
+    pub async fn query(&self, embedding: &[f32], top_k: usize, filter: &MetadataFilter) -> anyhow::Result<serde_json::Value> {
+        let url = self.endpoint("query").await?;
+        let mut payload = serde_json::json!({
+            "query_embeddings": [embedding],
+            "n_results": top_k
+        });
+        if let Some(clause) = where_clause(filter) {
+            payload["where"] = clause;
+        }
+
+        let resp = retry::send_with_retry(self.client.post(&url).json(&payload), &self.retry).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Chroma query failed: HTTP {} - {}", status, body);
+        }
+
+        let parsed: serde_json::Value = serde_json::from_str(&body)?;
+
+        Ok(parsed)
+    }
+
+    pub async fn delete_chunks(&self, ids: &[String]) -> anyhow::Result<()> {
+        let url = self.endpoint("delete").await?;
+        let payload = serde_json::json!({
+            "ids": ids
+        });
+
+        let resp = retry::send_with_retry(self.client.post(&url).json(&payload), &self.retry).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Failed to delete chunks {:?}: HTTP {} - {}", ids, status, body);
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_metadata(&self, ids: &[String], metadatas: &[serde_json::Value]) -> anyhow::Result<()> {
+        let url = self.endpoint("update").await?;
+        let payload = serde_json::json!({
+            "ids": ids,
+            "metadatas": metadatas
+        });
+
+        let resp = retry::send_with_retry(self.client.post(&url).json(&payload), &self.retry).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Failed to update chunks {:?}: HTTP {} - {}", ids, status, body);
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete_collection(&self) -> anyhow::Result<()> {
+        let url = format!("{}/{}", self.collections_url(), self.collection);
+        let resp = retry::send_with_retry(self.client.delete(&url), &self.retry).await?;
+        *self.resolved.lock().unwrap() = None;
+
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => Ok(()),
+            status => {
+                let body = resp.text().await?;
+                anyhow::bail!("Failed to delete collection '{}': HTTP {} - {}", self.collection, status, body)
+            }
         }
     }
 }
 
-pub async fn send_to_chroma(
-    client: &Client,
-    id: &str,
-    doc: &str,
-    embedding: &Vec<f32>,
-    path: &Path,
-    pb: &ProgressBar,
-) -> anyhow::Result<()> {
-    let config = config::load_config()?;
-    create_collection_if_missing(&client).await?;
-    let collection_id = get_collection_id(&client).await?;
-
-    let payload = ChromaV2AddRequest {
-        ids: vec![id.to_string()],
-        embeddings: vec![embedding.clone()],
-        documents: vec![doc.to_string()],
-        metadatas: vec![serde_json::json!({
-            "source": path.display().to_string()
-        })],
-    };
+/// Translates a filter into a Chroma `where` clause, or `None` when it has no conditions.
+fn where_clause(filter: &MetadataFilter) -> Option<serde_json::Value> {
+    let mut conditions = Vec::new();
+    if sources_in_where(filter) {
+        if let Some(sources) = &filter.sources {
+            conditions.push(serde_json::json!({ "source": { "$in": sources } }));
+        }
+        if !filter.excluded_sources.is_empty() {
+            conditions.push(serde_json::json!({ "source": { "$nin": filter.excluded_sources } }));
+        }
+    }
+    if !filter.languages.is_empty() {
+        conditions.push(serde_json::json!({ "language": { "$in": filter.languages } }));
+    }
+    if let Some(since) = filter.modified_since {
+        conditions.push(serde_json::json!({ "modified": { "$gte": since } }));
+    }
 
-    let add_url = format!(
-        "{}/api/v2/tenants/{}/databases/{}/collections/{}/add",
-        config.chroma_host, TENANT, DATABASE, collection_id
-    );
-
-    let resp = client.post(&add_url).json(&payload).send().await?;
-    let status = resp.status();
-    let body = resp.text().await?;
-
-    if !status.is_success() {
-        pb.println(format!(
-            "❌ Chroma error: HTTP {} - {}\nPayload ID: {}, Path: {}",
-            status,
-            body,
-            id,
-            path.display()
-        ));
-        anyhow::bail!("Failed to insert into Chroma");
-    }
-
-    pb.set_message(format!(
-        "✅ Indexed chunk: file={}, chars={}",
-        path.display(),
-        doc.len()
-    ));
-
-    Ok(())
+    match conditions.len() {
+        0 => None,
+        1 => conditions.pop(),
+        _ => Some(serde_json::json!({ "$and": conditions })),
+    }
+}
+
+fn sources_in_where(filter: &MetadataFilter) -> bool {
+    filter.sources.as_ref().map_or(0, Vec::len) + filter.excluded_sources.len() <= MAX_WHERE_SOURCES
 }
 
-pub async fn query_chroma(
-    client: &Client,
-    embedding: &Vec<f32>,
-    top_k: usize
-) -> anyhow::Result<serde_json::Value> {
-    let collection_id = get_collection_id(&client).await?;
-    let config = config::load_config()?;
-
-    let url = format!(
-        "{}/api/v2/tenants/{}/databases/{}/collections/{}/query",
-        config.chroma_host, TENANT, DATABASE, collection_id
-    );
-
-    let payload = serde_json::json!({
-        "query_embeddings": [embedding],
-        "n_results": top_k
-    });
-
-    let resp = client.post(&url).json(&payload).send().await?;
-    let body = resp.text().await?;
-    let parsed: serde_json::Value = serde_json::from_str(&body)?;
-
-    Ok(parsed)
+/// Converts Chroma's column-oriented query response into hits.
+fn parse_query_response(parsed: &serde_json::Value) -> anyhow::Result<Vec<QueryHit>> {
+    let column = |name: &str| {
+        parsed[name]
+            .as_array()
+            .and_then(|outer| outer.first())
+            .and_then(|inner| inner.as_array())
+            .ok_or_else(|| anyhow::anyhow!("No {} in response", name))
+    };
+
+    let ids = column("ids")?;
+    let docs = column("documents")?;
+    let metas = column("metadatas")?;
+    let dists = column("distances")?;
+
+    Ok(ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| QueryHit {
+            id: id.as_str().unwrap_or_default().to_string(),
+            document: docs[i].as_str().unwrap_or("<invalid UTF-8>").to_string(),
+            metadata: metas[i].clone(),
+            distance: dists[i].as_f64(),
+            score: None,
+        })
+        .collect())
 }
 
-pub async fn delete_chunk(client: &Client, id: &str) -> anyhow::Result<()> {
-    let config = config::load_config()?;
-    let collection_id = get_collection_id(client).await?;
+#[async_trait]
+impl VectorStore for ChromaClient {
+    async fn ensure_collection(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+        self.create_collection_if_missing(embedding).await
+    }
 
-    let url = format!(
-        "{}/api/v2/tenants/{}/databases/{}/collections/{}/delete",
-        config.chroma_host, TENANT, DATABASE, collection_id
-    );
+    async fn embedding_info(&self) -> anyhow::Result<Option<EmbeddingInfo>> {
+        Ok(EmbeddingInfo::from_metadata(&self.resolve().await?.metadata))
+    }
 
-    let payload = serde_json::json!({
-        "ids": [id]
-    });
+    async fn record_embedding(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+        ChromaClient::record_embedding(self, embedding).await
+    }
 
-    let resp = client.post(&url).json(&payload).send().await?;
-    let status = resp.status();
-    let body = resp.text().await?;
+    async fn delete_collection(&self) -> anyhow::Result<()> {
+        ChromaClient::delete_collection(self).await
+    }
 
-    if !status.is_success() {
-        anyhow::bail!("Failed to delete chunk {}: HTTP {} - {}", id, status, body);
+    async fn upsert(&self, records: &[VectorRecord]) -> anyhow::Result<()> {
+        ChromaClient::upsert(self, records).await
     }
 
-    Ok(())
+    async fn list(&self) -> anyhow::Result<Vec<(String, serde_json::Value)>> {
+        self.get_chunk_metadata().await
+    }
+
+    async fn query(&self, embedding: &[f32], top_k: usize, filter: &MetadataFilter) -> anyhow::Result<Vec<QueryHit>> {
+        if sources_in_where(filter) {
+            let parsed = ChromaClient::query(self, embedding, top_k, filter).await?;
+            return parse_query_response(&parsed);
+        }
+
+        let parsed = ChromaClient::query(self, embedding, top_k * SOURCE_FILTER_OVERFETCH, filter).await?;
+        let mut hits = parse_query_response(&parsed)?;
+        hits.retain(|hit| filter.matches_metadata(&hit.metadata));
+        hits.truncate(top_k);
+        Ok(hits)
+    }
+
+    async fn delete(&self, ids: &[String]) -> anyhow::Result<()> {
+        self.delete_chunks(ids).await
+    }
+
+    async fn update_metadata(&self, ids: &[String], metadatas: &[serde_json::Value]) -> anyhow::Result<()> {
+        ChromaClient::update_metadata(self, ids, metadatas).await
+    }
 }
@@ -0,0 +1,262 @@
+use tree_sitter::{Language, Node, Parser};
+
+const MAX_CHUNK_LINES: usize = 80;
+const MAX_CHUNK_BYTES: usize = 6_000;
+
+/// Node kinds that wrap a single declaration (e.g. `export class ...`).
+const WRAPPER_FIELDS: [&str; 2] = ["declaration", "definition"];
+
+struct LanguageSpec {
+    language: Language,
+    /// Node kinds emitted as their own chunk.
+    symbols: &'static [&'static str],
+    /// Symbols whose body can be split into child symbols when oversized.
+    containers: &'static [&'static str],
+    /// Nodes that belong to the symbol following them (doc comments, attributes).
+    attachments: &'static [&'static str],
+}
+
+impl LanguageSpec {
+    fn is_symbol(&self, kind: &str) -> bool {
+        self.symbols.contains(&kind)
+    }
+}
+
+const RUST_SYMBOLS: &[&str] = &[
+    "function_item",
+    "function_signature_item",
+    "impl_item",
+    "struct_item",
+    "enum_item",
+    "union_item",
+    "trait_item",
+    "mod_item",
+    "macro_definition",
+    "const_item",
+    "static_item",
+    "type_item",
+];
+
+const TS_SYMBOLS: &[&str] = &[
+    "function_declaration",
+    "generator_function_declaration",
+    "class_declaration",
+    "abstract_class_declaration",
+    "interface_declaration",
+    "type_alias_declaration",
+    "enum_declaration",
+    "internal_module",
+    "module",
+    "method_definition",
+    "public_field_definition",
+    "lexical_declaration",
+    "export_statement",
+];
+
+const PYTHON_SYMBOLS: &[&str] = &[
+    "function_definition",
+    "class_definition",
+    "decorated_definition",
+];
+
+const GO_SYMBOLS: &[&str] = &[
+    "function_declaration",
+    "method_declaration",
+    "type_declaration",
+    "const_declaration",
+    "var_declaration",
+];
+
+fn language_for(extension: &str) -> Option<LanguageSpec> {
+    let spec = match extension {
+        "rs" => LanguageSpec {
+            language: tree_sitter_rust::LANGUAGE.into(),
+            symbols: RUST_SYMBOLS,
+            containers: &["impl_item", "trait_item", "mod_item"],
+            attachments: &["line_comment", "block_comment", "attribute_item"],
+        },
+        "ts" | "mts" | "cts" => LanguageSpec {
+            language: tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            symbols: TS_SYMBOLS,
+            containers: &["class_declaration", "abstract_class_declaration", "internal_module", "module"],
+            attachments: &["comment", "decorator"],
+        },
+        "tsx" => LanguageSpec {
+            language: tree_sitter_typescript::LANGUAGE_TSX.into(),
+            symbols: TS_SYMBOLS,
+            containers: &["class_declaration", "abstract_class_declaration", "internal_module", "module"],
+            attachments: &["comment", "decorator"],
+        },
+        "js" | "jsx" | "mjs" | "cjs" => LanguageSpec {
+            language: tree_sitter_javascript::LANGUAGE.into(),
+            symbols: TS_SYMBOLS,
+            containers: &["class_declaration"],
+            attachments: &["comment", "decorator"],
+        },
+        "py" => LanguageSpec {
+            language: tree_sitter_python::LANGUAGE.into(),
+            symbols: PYTHON_SYMBOLS,
+            containers: &["class_definition"],
+            attachments: &["comment"],
+        },
+        "go" => LanguageSpec {
+            language: tree_sitter_go::LANGUAGE.into(),
+            symbols: GO_SYMBOLS,
+            containers: &[],
+            attachments: &["comment"],
+        },
+        _ => return None,
+    };
+
+    Some(spec)
+}
+
+/// Splits source code into one chunk per top-level symbol (function, struct,
+/// class, impl block, ...). Symbols larger than the chunk limit are split into
+/// their members when possible, otherwise into line windows.
+///
+/// Returns inclusive row ranges, or `None` when the language is unsupported or
+/// the file can't be parsed.
+pub fn chunk_source(text: &str, extension: &str) -> Option<Vec<(usize, usize)>> {
+    let spec = language_for(extension)?;
+    let mut parser = Parser::new();
+    parser.set_language(&spec.language).ok()?;
+    let tree = parser.parse(text, None)?;
+
+    let mut chunker = Chunker {
+        spec: &spec,
+        lines: text.lines().collect(),
+        spans: Vec::new(),
+        next_row: 0,
+    };
+    chunker.visit_children(tree.root_node());
+
+    Some(chunker.spans)
+}
+
+struct Chunker<'a> {
+    spec: &'a LanguageSpec,
+    lines: Vec<&'a str>,
+    /// Inclusive row ranges, in file order and never overlapping.
+    spans: Vec<(usize, usize)>,
+    next_row: usize,
+}
+
+impl Chunker<'_> {
+    fn visit_children(&mut self, parent: Node) {
+        let mut gap_start: Option<usize> = None;
+        let mut lead_start: Option<usize> = None;
+
+        let mut cursor = parent.walk();
+        for child in parent.children(&mut cursor) {
+            let kind = child.kind();
+            let row = child.start_position().row;
+
+            if self.spec.is_symbol(kind) {
+                let start = lead_start.take().unwrap_or(row);
+                if let Some(gap) = gap_start.take() {
+                    if start > 0 {
+                        self.push_span(gap, start - 1);
+                    }
+                }
+                self.visit_symbol(child, start);
+            } else if self.spec.attachments.contains(&kind) {
+                lead_start.get_or_insert(row);
+            } else {
+                // Comments not followed by a symbol are ordinary code
+                if let Some(lead) = lead_start.take() {
+                    gap_start.get_or_insert(lead);
+                }
+                gap_start.get_or_insert(row);
+            }
+        }
+
+        if let Some(lead) = lead_start {
+            gap_start.get_or_insert(lead);
+        }
+        if let Some(gap) = gap_start {
+            self.push_span(gap, parent.end_position().row);
+        }
+    }
+
+    fn visit_symbol(&mut self, node: Node, start: usize) {
+        let end = node.end_position().row;
+        if !self.is_oversized(start, end) {
+            self.push_span(start, end);
+            return;
+        }
+
+        for field in WRAPPER_FIELDS {
+            if let Some(inner) = node.child_by_field_name(field) {
+                if self.spec.is_symbol(inner.kind()) {
+                    self.visit_symbol(inner, start);
+                    return;
+                }
+            }
+        }
+
+        if self.spec.containers.contains(&node.kind()) {
+            if let Some(body) = node.child_by_field_name("body") {
+                let body_row = body.start_position().row;
+                // Brace-delimited bodies open on the header line; indented ones start below it
+                let header_end = match body.named_child(0) {
+                    Some(first) if first.start_position().row == body_row && body_row > start => body_row - 1,
+                    _ => body_row,
+                };
+                self.push_span(start, header_end);
+                self.visit_children(body);
+                return;
+            }
+        }
+
+        self.push_span(start, end);
+    }
+
+    fn is_oversized(&self, start: usize, end: usize) -> bool {
+        if end + 1 - start > MAX_CHUNK_LINES {
+            return true;
+        }
+        let end = end.min(self.lines.len().saturating_sub(1));
+        self.lines
+            .get(start..=end)
+            .map(|lines| lines.iter().map(|l| l.len() + 1).sum::<usize>() > MAX_CHUNK_BYTES)
+            .unwrap_or(false)
+    }
+
+    /// Records rows `start..=end`, skipping rows already emitted and splitting
+    /// ranges that exceed the chunk limits into line windows.
+    fn push_span(&mut self, start: usize, end: usize) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let end = end.min(self.lines.len() - 1);
+        let start = start.max(self.next_row);
+        if start > end {
+            return;
+        }
+        self.next_row = end + 1;
+
+        let mut window_start = start;
+        while window_start <= end {
+            let mut window_end = window_start;
+            let mut bytes = self.lines[window_start].len();
+            while window_end < end
+                && window_end + 1 - window_start < MAX_CHUNK_LINES
+                && bytes + self.lines[window_end + 1].len() < MAX_CHUNK_BYTES
+            {
+                window_end += 1;
+                bytes += self.lines[window_end].len() + 1;
+            }
+
+            // Skip spans made only of closing braces and blank lines
+            let has_content = self.lines[window_start..=window_end]
+                .iter()
+                .any(|line| line.chars().any(char::is_alphanumeric));
+            if has_content {
+                self.spans.push((window_start, window_end));
+            }
+
+            window_start = window_end + 1;
+        }
+    }
+}
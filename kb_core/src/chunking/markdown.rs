@@ -0,0 +1,183 @@
+const MAX_CHUNK_LINES: usize = 60;
+const MAX_CHUNK_BYTES: usize = 4_000;
+
+/// Splits Markdown into one chunk per heading section. Each chunk carries the
+/// heading path leading to it (e.g. `README > Configuration > ChromaDB`) and
+/// fenced code blocks are never split.
+///
+/// Returns inclusive row ranges paired with their breadcrumb.
+pub fn chunk_markdown(text: &str, title: &str) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+    let mut section_start = 0;
+
+    for (row, line) in lines.iter().enumerate() {
+        if let Some(open) = fence {
+            if closes_fence(line, open) {
+                fence = None;
+            }
+            continue;
+        }
+
+        if let Some(open) = opens_fence(line) {
+            fence = Some(open);
+            continue;
+        }
+
+        if let Some((level, heading)) = parse_heading(line) {
+            push_section(&mut chunks, &lines, section_start, row, &breadcrumb(title, &headings));
+            headings.retain(|(l, _)| *l < level);
+            headings.push((level, heading));
+            section_start = row;
+        }
+    }
+
+    push_section(&mut chunks, &lines, section_start, lines.len(), &breadcrumb(title, &headings));
+    chunks
+}
+
+fn breadcrumb(title: &str, headings: &[(usize, String)]) -> String {
+    std::iter::once(title)
+        .chain(headings.iter().map(|(_, h)| h.as_str()))
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+fn parse_heading(line: &str) -> Option<(usize, String)> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+
+    let rest = &trimmed[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+
+    let heading = rest.trim().trim_end_matches('#').trim();
+    Some((level, heading.to_string()))
+}
+
+fn opens_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = trimmed.chars().take_while(|&c| c == marker).count();
+    (len >= 3).then_some((marker, len))
+}
+
+fn closes_fence(line: &str, (marker, len): (char, usize)) -> bool {
+    let trimmed = line.trim();
+    trimmed.chars().take_while(|&c| c == marker).count() >= len
+        && trimmed.chars().all(|c| c == marker)
+}
+
+/// Emits rows `start..end` as one or more chunks, splitting oversized sections
+/// on blank lines outside of code fences.
+fn push_section(chunks: &mut Vec<(usize, usize, String)>, lines: &[&str], start: usize, end: usize, breadcrumb: &str) {
+    let section = &lines[start..end];
+    let has_body = section
+        .iter()
+        .any(|line| !line.trim().is_empty() && parse_heading(line).is_none());
+    if !has_body {
+        return;
+    }
+
+    // Blocks are paragraphs or whole fenced code blocks, as row ranges
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    let mut block_start = 0;
+    let mut fence = None;
+    for (i, line) in section.iter().enumerate() {
+        match fence {
+            Some(open) => {
+                if closes_fence(line, open) {
+                    fence = None;
+                }
+            }
+            None if line.trim().is_empty() => {
+                if block_start < i {
+                    blocks.push((block_start, i));
+                }
+                block_start = i + 1;
+            }
+            None => fence = opens_fence(line),
+        }
+    }
+    if block_start < section.len() {
+        blocks.push((block_start, section.len()));
+    }
+
+    let mut current: Option<(usize, usize)> = None;
+    let mut current_bytes = 0;
+    for (block_start, block_end) in blocks {
+        let block_bytes = section[block_start..block_end].iter().map(|l| l.len() + 1).sum::<usize>();
+
+        if let Some((chunk_start, chunk_end)) = current {
+            if block_end - chunk_start > MAX_CHUNK_LINES || current_bytes + block_bytes > MAX_CHUNK_BYTES {
+                chunks.push((start + chunk_start, start + chunk_end - 1, breadcrumb.to_string()));
+                current = None;
+                current_bytes = 0;
+            }
+        }
+
+        let chunk_start = current.map_or(block_start, |(s, _)| s);
+        current = Some((chunk_start, block_end));
+        current_bytes += block_bytes;
+    }
+
+    if let Some((chunk_start, chunk_end)) = current {
+        chunks.push((start + chunk_start, start + chunk_end - 1, breadcrumb.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_each_section_with_its_heading_path() {
+        let text = "Intro\n\n# Setup\n\nInstall it.\n\n## Config\n\nSet the key.\n\n# Usage\n\nRun it.\n";
+        let chunks = chunk_markdown(text, "README");
+        assert_eq!(
+            chunks,
+            [
+                (0, 0, "README".to_string()),
+                (2, 4, "README > Setup".to_string()),
+                (6, 8, "README > Setup > Config".to_string()),
+                (10, 12, "README > Usage".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_headings_inside_code_fences() {
+        let text = "# Shell\n\n```sh\n# not a heading\necho hi\n```\n";
+        let chunks = chunk_markdown(text, "doc");
+        assert_eq!(chunks, [(0, 5, "doc > Shell".to_string())]);
+    }
+
+    #[test]
+    fn skips_sections_without_a_body() {
+        let text = "# Empty\n## Also empty\n\n### Filled\ntext\n";
+        let chunks = chunk_markdown(text, "doc");
+        assert_eq!(chunks, [(3, 4, "doc > Empty > Also empty > Filled".to_string())]);
+    }
+
+    #[test]
+    fn splits_long_sections_between_paragraphs() {
+        let paragraph = "word ".repeat(200);
+        let text = format!("# Long\n\n{}\n\n{}\n\n{}\n\n{}\n\n{}\n", paragraph, paragraph, paragraph, paragraph, paragraph);
+        let chunks = chunk_markdown(&text, "doc");
+        assert!(chunks.len() > 1);
+        for (start, end, _) in &chunks {
+            let bytes: usize = text.lines().skip(*start).take(end - start + 1).map(|l| l.len() + 1).sum();
+            assert!(bytes <= MAX_CHUNK_BYTES);
+        }
+    }
+}
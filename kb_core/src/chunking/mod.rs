@@ -0,0 +1,129 @@
+pub mod code;
+pub mod markdown;
+
+use std::path::Path;
+
+const LINES_PER_CHUNK: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub content: String,
+    /// Heading path of the section the chunk came from, e.g. `README > Configuration`.
+    pub breadcrumb: Option<String>,
+    /// 1-based, inclusive line range within the file.
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Byte range within the file.
+    pub start_byte: usize,
+    pub end_byte: usize,
+}
+
+impl Chunk {
+    /// Text sent to the embedding model. Includes the breadcrumb so a section is
+    /// retrievable by the headings above it.
+    pub fn embedding_text(&self) -> String {
+        match &self.breadcrumb {
+            Some(breadcrumb) => format!("{}\n\n{}", breadcrumb, self.content),
+            None => self.content.clone(),
+        }
+    }
+}
+
+/// Splits a file into chunks for embedding, using a syntax-aware chunker when
+/// the language is supported and falling back to fixed line windows otherwise.
+pub fn chunk_file(path: &Path, text: &str) -> Vec<Chunk> {
+    let extension = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let lines = LineIndex::new(text);
+
+    if matches!(extension, "md" | "markdown") {
+        let title = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("document");
+        return markdown::chunk_markdown(text, title)
+            .into_iter()
+            .map(|(start, end, breadcrumb)| lines.chunk(start, end, Some(breadcrumb)))
+            .collect();
+    }
+
+    code::chunk_source(text, extension)
+        .unwrap_or_else(|| line_windows(lines.len()))
+        .into_iter()
+        .map(|(start, end)| lines.chunk(start, end, None))
+        .filter(|chunk| !chunk.content.trim().is_empty())
+        .collect()
+}
+
+/// Language name stored with each chunk for filtering, e.g. `rust` for `rs`.
+/// Extensions without a known name are used as-is.
+pub fn language_name(extension: &str) -> String {
+    let extension = extension.to_lowercase();
+    let name = match extension.as_str() {
+        "rs" => "rust",
+        "ts" | "mts" | "cts" | "tsx" => "typescript",
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "py" => "python",
+        "go" => "go",
+        "md" | "markdown" => "markdown",
+        "html" | "htm" => "html",
+        _ => return extension,
+    };
+    name.to_string()
+}
+
+fn line_windows(line_count: usize) -> Vec<(usize, usize)> {
+    (0..line_count)
+        .step_by(LINES_PER_CHUNK)
+        .map(|start| (start, (start + LINES_PER_CHUNK).min(line_count) - 1))
+        .collect()
+}
+
+/// Maps zero-based rows to their text and byte offsets.
+struct LineIndex<'a> {
+    text: &'a str,
+    lines: Vec<&'a str>,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        LineIndex {
+            text,
+            lines: text.lines().collect(),
+            starts,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn chunk(&self, start: usize, end: usize, breadcrumb: Option<String>) -> Chunk {
+        let end_byte = self
+            .starts
+            .get(end + 1)
+            .map(|&next| next - 1)
+            .unwrap_or(self.text.len());
+        let end_byte = if self.text[..end_byte].ends_with('\r') {
+            end_byte - 1
+        } else {
+            end_byte
+        };
+
+        Chunk {
+            content: self.lines[start..=end].join("\n"),
+            breadcrumb,
+            start_line: start + 1,
+            end_line: end + 1,
+            start_byte: self.starts[start],
+            end_byte,
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use super::query::{context_chunk, sources_footer, QueryContext, QueryOptions};
+use crate::cli::FilterArgs;
+use crate::project::Project;
+use crate::search::SearchMode;
+use crate::state::{hash_query_context, StateLock};
+use crate::store::{QueryHit, SearchResult};
+use crate::utils;
+use ansi_to_tui::IntoText;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use reqwest::Client;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Lines of file context shown around a chunk at first, and per `+` or `-`.
+const CONTEXT_STEP: usize = 5;
+/// Lines kept visible above a chunk when the preview scrolls to it.
+const SCROLL_MARGIN: usize = 2;
+
+const HELP: &str = "↑↓ move  PgUp/PgDn scroll  +/- context  space mark  a ask  / refine  e edit  q quit";
+
+/// Shows search results in a full-screen browser with a preview of each chunk
+/// in its file.
+pub async fn handle_browse(
+    client: &Client,
+    query: &str,
+    top_k: usize,
+    mode: &str,
+    project: Option<String>,
+    filters: &FilterArgs,
+) -> anyhow::Result<()> {
+    let options = QueryOptions {
+        top_k,
+        format: "smart".to_string(),
+        mode: SearchMode::parse(mode)?,
+        filters: filters.clone(),
+    };
+    let project = Project::resolve(project.as_deref(), &std::env::current_dir()?)?;
+    let mut context = QueryContext::load(client, project)?;
+
+    // Search before taking over the terminal so errors print normally
+    let mut browser = Browser::new(query.to_string());
+    let hits = search(&mut context, &options, query).await?;
+    browser.show_results(hits);
+    browser.warn_missing(&context);
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut browser, &mut context, &options).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    browser: &mut Browser,
+    context: &mut QueryContext,
+    options: &QueryOptions,
+) -> anyhow::Result<()> {
+    loop {
+        browser.refresh_preview(context);
+        terminal.draw(|frame| browser.draw(frame))?;
+
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        // Editing the query
+        if let Some(input) = &mut browser.input {
+            match key.code {
+                KeyCode::Enter => {
+                    let query = input.trim().to_string();
+                    browser.input = None;
+                    if query.is_empty() {
+                        continue;
+                    }
+                    browser.status = format!("⏳ Searching for \"{}\"…", query);
+                    terminal.draw(|frame| browser.draw(frame))?;
+                    browser.query = query;
+                    match search(context, options, &browser.query).await {
+                        Ok(hits) => {
+                            browser.show_results(hits);
+                            browser.warn_missing(context);
+                        }
+                        Err(e) => browser.status = format!("❌ {:#}", e),
+                    }
+                }
+                KeyCode::Esc => browser.input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            continue;
+        }
+
+        // Reading an answer
+        if browser.answer.is_some() {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') => {
+                    browser.answer = None;
+                    browser.scroll = browser.preview.as_ref().map_or(0, |p| p.chunk_offset);
+                }
+                code => browser.scroll_by(code),
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Up | KeyCode::Char('k') => browser.select_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => browser.select_by(1),
+            KeyCode::Char('+') | KeyCode::Char('=') => browser.context_lines += CONTEXT_STEP,
+            KeyCode::Char('-') => browser.context_lines = browser.context_lines.saturating_sub(CONTEXT_STEP),
+            KeyCode::Char(' ') => browser.toggle_mark(),
+            KeyCode::Char('/') => browser.input = Some(browser.query.clone()),
+            KeyCode::Char('e') | KeyCode::Enter => {
+                if let Some(hit) = browser.current() {
+                    let result = SearchResult::from_hit(0, hit);
+                    let line = result.start_line.unwrap_or(1);
+                    browser.status = match open_in_editor(terminal, &context.file_path(result.source), line) {
+                        Ok(()) => String::new(),
+                        Err(e) => format!("❌ {:#}", e),
+                    };
+                    // The file may have changed
+                    browser.preview = None;
+                }
+            }
+            KeyCode::Char('a') => {
+                let hits = browser.marked_or_current();
+                if hits.is_empty() {
+                    continue;
+                }
+                let noun = if hits.len() == 1 { "chunk" } else { "chunks" };
+                browser.status = format!("⏳ Asking about {} {}…", hits.len(), noun);
+                terminal.draw(|frame| browser.draw(frame))?;
+                match ask(context, &browser.query, &hits).await {
+                    Ok(answer) => {
+                        browser.answer = Some(answer);
+                        browser.scroll = 0;
+                        browser.status = String::new();
+                    }
+                    Err(e) => browser.status = format!("❌ {:#}", e),
+                }
+            }
+            code => browser.scroll_by(code),
+        }
+    }
+
+    Ok(())
+}
+
+/// The highlighted lines around a chunk, cached until the selection or the
+/// amount of context changes.
+struct Preview {
+    hit: usize,
+    context_lines: usize,
+    text: Text<'static>,
+    /// Line in `text` where the chunk starts.
+    chunk_offset: u16,
+}
+
+struct Browser {
+    query: String,
+    hits: Vec<QueryHit>,
+    list: ListState,
+    /// Hits marked to send to the LLM.
+    marked: BTreeSet<usize>,
+    context_lines: usize,
+    preview: Option<Preview>,
+    scroll: u16,
+    /// The query being edited, while refining it.
+    input: Option<String>,
+    answer: Option<Text<'static>>,
+    status: String,
+}
+
+impl Browser {
+    fn new(query: String) -> Self {
+        Browser {
+            query,
+            hits: Vec::new(),
+            list: ListState::default(),
+            marked: BTreeSet::new(),
+            context_lines: CONTEXT_STEP,
+            preview: None,
+            scroll: 0,
+            input: None,
+            answer: None,
+            status: String::new(),
+        }
+    }
+
+    /// Shows the last search's missing-files warning in the status line.
+    fn warn_missing(&mut self, context: &QueryContext) {
+        if let Some(warning) = context.missing_files_warning() {
+            self.status = warning;
+        }
+    }
+
+    fn show_results(&mut self, hits: Vec<QueryHit>) {
+        self.status = if hits.is_empty() { "No results".to_string() } else { String::new() };
+        self.list.select(if hits.is_empty() { None } else { Some(0) });
+        self.hits = hits;
+        self.marked.clear();
+        self.preview = None;
+        self.answer = None;
+    }
+
+    fn current(&self) -> Option<&QueryHit> {
+        self.list.selected().and_then(|i| self.hits.get(i))
+    }
+
+    fn select_by(&mut self, delta: isize) {
+        if let Some(i) = self.list.selected() {
+            let last = self.hits.len().saturating_sub(1);
+            self.list.select(Some(i.saturating_add_signed(delta).min(last)));
+        }
+    }
+
+    fn scroll_by(&mut self, code: KeyCode) {
+        self.scroll = match code {
+            KeyCode::PageDown => self.scroll.saturating_add(20),
+            KeyCode::PageUp => self.scroll.saturating_sub(20),
+            KeyCode::Char('J') => self.scroll.saturating_add(1),
+            KeyCode::Char('K') => self.scroll.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') if self.answer.is_some() => self.scroll.saturating_add(1),
+            KeyCode::Up | KeyCode::Char('k') if self.answer.is_some() => self.scroll.saturating_sub(1),
+            _ => self.scroll,
+        };
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(i) = self.list.selected() {
+            if !self.marked.remove(&i) {
+                self.marked.insert(i);
+            }
+            self.select_by(1);
+        }
+    }
+
+    /// The marked hits, or the highlighted one when none are marked.
+    fn marked_or_current(&self) -> Vec<QueryHit> {
+        if self.marked.is_empty() {
+            return self.current().cloned().into_iter().collect();
+        }
+        self.marked.iter().map(|&i| self.hits[i].clone()).collect()
+    }
+
+    fn refresh_preview(&mut self, context: &QueryContext) {
+        let Some(i) = self.list.selected() else {
+            self.preview = None;
+            return;
+        };
+        if self.preview.as_ref().is_some_and(|p| p.hit == i && p.context_lines == self.context_lines) {
+            return;
+        }
+
+        let hit = &self.hits[i];
+        let path = context.file_path(SearchResult::from_hit(0, hit).source);
+        let (text, chunk_offset) = preview(hit, &path, self.context_lines);
+        self.scroll = chunk_offset;
+        self.preview = Some(Preview { hit: i, context_lines: self.context_lines, text, chunk_offset });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main);
+
+        let items: Vec<ListItem> = self
+            .hits
+            .iter()
+            .enumerate()
+            .map(|(i, hit)| {
+                let r = SearchResult::from_hit(i + 1, hit);
+                let mark = if self.marked.contains(&i) { "● " } else { "  " };
+                let mut spans = vec![Span::styled(mark, Style::default().fg(Color::Green)), Span::raw(r.location)];
+                if let Some(breadcrumb) = r.breadcrumb {
+                    spans.push(Span::styled(format!(" — {}", breadcrumb), Style::default().fg(Color::DarkGray)));
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(" {} ", self.query)))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let (title, text, wrap) = match (&self.answer, &self.preview) {
+            (Some(answer), _) => (" Answer (Esc to close) ".to_string(), answer.clone(), true),
+            (None, Some(preview)) => {
+                let title = self.current().map(|hit| SearchResult::from_hit(0, hit).location).unwrap_or_default();
+                (format!(" {} ", title), preview.text.clone(), false)
+            }
+            (None, None) => (String::new(), Text::default(), false),
+        };
+        let mut paragraph = Paragraph::new(text)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .scroll((self.scroll, 0));
+        if wrap {
+            paragraph = paragraph.wrap(Wrap { trim: false });
+        }
+        frame.render_widget(paragraph, right);
+
+        let status_line = match &self.input {
+            Some(input) => Line::from(vec![Span::styled("/ ", Style::default().fg(Color::Yellow)), Span::raw(format!("{}▏", input))]),
+            None if !self.status.is_empty() => Line::raw(self.status.as_str()),
+            None => Line::styled(HELP, Style::default().fg(Color::DarkGray)),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+}
+
+async fn search(context: &mut QueryContext, options: &QueryOptions, query: &str) -> anyhow::Result<Vec<QueryHit>> {
+    let query_embedding = context.embed_query(query, options.mode).await?;
+    match context.search(query, query_embedding.as_deref(), options).await? {
+        Some(hits) => Ok(hits),
+        None => anyhow::bail!("No indexed files match --path {}", options.filters.paths.join(", ")),
+    }
+}
+
+/// Highlights the chunk with `context_lines` of its file at `path` around it,
+/// numbering lines and marking the chunk's in the gutter. Returns the text and
+/// the line to scroll to. Falls back to the indexed chunk when the file can't
+/// be read or has shrunk.
+fn preview(hit: &QueryHit, path: &Path, context_lines: usize) -> (Text<'static>, u16) {
+    let r = SearchResult::from_hit(0, hit);
+    let file = fs::read_to_string(path).ok();
+    let lines: Vec<&str> = file.as_deref().map(|f| f.lines().collect()).unwrap_or_default();
+
+    let (start, end) = match (r.start_line, r.end_line) {
+        (Some(start), Some(end)) if start >= 1 && end as usize <= lines.len() => (start as usize, end as usize),
+        _ => return (highlight_lines(r.content, r.source, None), 0),
+    };
+
+    let first = start.saturating_sub(context_lines).max(1);
+    let last = (end + context_lines).min(lines.len());
+    let snippet = lines[first - 1..last].join("\n");
+    let text = highlight_lines(&snippet, r.source, Some((first, start, end)));
+    (text, (start - first).saturating_sub(SCROLL_MARGIN) as u16)
+}
+
+/// Highlights `code`, adding a gutter with line numbers counted from the first
+/// of `lines` and a bar beside the lines from the second to the third.
+fn highlight_lines(code: &str, source: &str, lines: Option<(usize, usize, usize)>) -> Text<'static> {
+    let highlighted = utils::highlight_syntax(code, source);
+    let mut text = Text::default();
+
+    for (offset, line) in highlighted.lines().enumerate() {
+        let mut spans = Vec::new();
+        if let Some((first, start, end)) = lines {
+            let number = first + offset;
+            let (bar, style) = if (start..=end).contains(&number) {
+                ("▌", Style::default().fg(Color::Yellow))
+            } else {
+                (" ", Style::default().fg(Color::DarkGray))
+            };
+            spans.push(Span::styled(format!("{:>5} {} ", number, bar), style));
+        }
+        if let Some(parsed) = line.to_string().into_text().ok().and_then(|t| t.lines.into_iter().next()) {
+            spans.extend(parsed.spans);
+        }
+        text.lines.push(Line::from(spans));
+    }
+
+    text
+}
+
+/// Suspends the browser to open `source` at `line` in `$EDITOR`.
+fn open_in_editor(terminal: &mut DefaultTerminal, source: &Path, line: u64) -> anyhow::Result<()> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let mut parts = editor.split_whitespace();
+    let Some(program) = parts.next() else {
+        anyhow::bail!("$EDITOR is empty");
+    };
+
+    terminal::disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    let status = Command::new(program).args(parts).arg(format!("+{}", line)).arg(source).status();
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    terminal::enable_raw_mode()?;
+    terminal.clear()?;
+
+    let status = status.map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+/// Answers the query from `hits`, adding the exchange to the active session.
+/// Returns the rendered answer followed by the sources it cites.
+async fn ask(context: &mut QueryContext, query: &str, hits: &[QueryHit]) -> anyhow::Result<Text<'static>> {
+    let results: Vec<SearchResult> = hits
+        .iter()
+        .enumerate()
+        .map(|(i, hit)| SearchResult::from_hit(i + 1, hit))
+        .collect();
+    let context_chunks: Vec<String> = results.iter().map(context_chunk).collect();
+    let context_hash = hash_query_context(query, &context_chunks);
+
+    // Re-read the sessions other kb processes may have changed while browsing
+    let _lock = StateLock::sessions(&context.config_dir)?;
+    context.reload_sessions()?;
+
+    // Answers to hand-picked chunks aren't cached: the similarity lookup
+    // would return them for the query regardless of the chunks chosen
+    let answer = match context.cache.get_cached_answer(query, &context_hash)? {
+        Some(cached) => cached,
+        None => context.answer(query, &context_chunks).await?,
+    };
+
+    if context.sessions.active_session.is_none() {
+        context.sessions.create_session();
+    }
+    context.sessions.add_interaction(query.to_string(), answer.clone())?;
+    context.sessions.save(&context.config_dir)?;
+
+    let mut text = utils::render_markdown_highlighted(&answer);
+    text.push_str(&sources_footer(&answer, &results));
+
+    Ok(text.into_text()?)
+}
@@ -0,0 +1,166 @@
+use super::query::{print_results, QueryContext, QueryOptions};
+use super::session::print_sessions;
+use crate::cli::FilterArgs;
+use crate::config;
+use crate::project::Project;
+use crate::search::SearchMode;
+use crate::state::StateLock;
+use crate::store::SearchResult;
+use reqwest::Client;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const HISTORY_FILE: &str = "chat-history.txt";
+const FORMATS: &[&str] = &["pretty", "json", "markdown", "smart"];
+
+const HELP: &str = "\
+Commands:
+  /new            Start a new session
+  /sessions       List sessions
+  /topk <n>       Set the number of results to retrieve
+  /format <f>     Set the output format (pretty, json, markdown, smart)
+  /mode <m>       Set the search mode (semantic, lexical, hybrid)
+  /sources        Show the chunks retrieved for the last question
+  /help           Show this help
+  /quit           Leave the chat";
+
+/// Runs an interactive chat, answering each line as `kb query` would and
+/// adding the exchange to the active session.
+pub async fn handle_chat(
+    client: &Client,
+    top_k: usize,
+    format: &str,
+    mode: &str,
+    project: Option<String>,
+    filters: &FilterArgs,
+) -> anyhow::Result<()> {
+    let mut options = QueryOptions {
+        top_k,
+        format: parse_format(format)?,
+        mode: SearchMode::parse(mode)?,
+        filters: filters.clone(),
+    };
+    let project = Project::resolve(project.as_deref(), &std::env::current_dir()?)?;
+    let project_name = project.name.clone();
+    let mut context = QueryContext::load(client, project)?;
+
+    if context.sessions.active_session.is_none() {
+        let _lock = StateLock::sessions(&context.config_dir)?;
+        let new_id = context.sessions.create_session();
+        context.sessions.save(&context.config_dir)?;
+        println!("🆕 Created default session: {}", new_id);
+    }
+
+    let history_path = config::get_config_dir()?.join(HISTORY_FILE);
+    let mut editor = DefaultEditor::new()?;
+    // No history yet on the first run
+    let _ = editor.load_history(&history_path);
+
+    println!("💬 Chatting with project '{}'. Type /help for commands, /quit or Ctrl-D to leave.", project_name);
+
+    loop {
+        let prompt = match context.sessions.get_active_session() {
+            Some(session) => format!("kb [{}]> ", &session.id[..8]),
+            None => "kb> ".to_string(),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+
+        if let Some(command) = line.strip_prefix('/') {
+            let mut parts = command.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            let arg = parts.next();
+
+            match (name, arg) {
+                ("quit" | "exit" | "q", _) => break,
+                ("help", _) => println!("{}", HELP),
+                ("new", _) => {
+                    let _lock = StateLock::sessions(&context.config_dir)?;
+                    context.reload_sessions()?;
+                    let new_id = context.sessions.create_session();
+                    context.sessions.save(&context.config_dir)?;
+                    context.last_hits.clear();
+                    println!("🆕 Created new session: {}", new_id);
+                }
+                ("sessions", _) => print_sessions(&context.sessions),
+                ("topk", Some(n)) => match n.parse::<usize>() {
+                    Ok(n) if n > 0 => {
+                        options.top_k = n;
+                        println!("Retrieving {} results per question", n);
+                    }
+                    _ => println!("⚠️ /topk takes a positive number"),
+                },
+                ("format", Some(format)) => match parse_format(format) {
+                    Ok(format) => {
+                        println!("Output format: {}", format);
+                        options.format = format;
+                    }
+                    Err(e) => println!("⚠️ {}", e),
+                },
+                ("mode", Some(mode)) => match SearchMode::parse(mode) {
+                    Ok(parsed) => {
+                        options.mode = parsed;
+                        println!("Search mode: {}", mode);
+                    }
+                    Err(e) => println!("⚠️ {}", e),
+                },
+                ("sources", _) => {
+                    if context.last_hits.is_empty() {
+                        println!("No sources to show; ask a question first");
+                    } else {
+                        let results: Vec<SearchResult> = context
+                            .last_hits
+                            .iter()
+                            .enumerate()
+                            .map(|(i, hit)| SearchResult::from_hit(i + 1, hit))
+                            .collect();
+                        print_results(&results);
+                    }
+                }
+                ("topk" | "format" | "mode", None) => println!("⚠️ /{} needs a value; see /help", name),
+                _ => println!("⚠️ Unknown command /{}; see /help", name),
+            }
+            continue;
+        }
+
+        // Hold the lock only while answering so other kb processes can run between questions
+        let _lock = StateLock::sessions(&context.config_dir)?;
+        if let Err(e) = ask(&mut context, line, &options).await {
+            eprintln!("❌ {:#}", e);
+        }
+        println!();
+    }
+
+    if let Err(e) = editor.save_history(&history_path) {
+        eprintln!("⚠️ Failed to save chat history: {}", e);
+    }
+    Ok(())
+}
+
+/// Answers a line with the sessions re-read, since other `kb` processes may
+/// have changed them since the last question.
+async fn ask(context: &mut QueryContext, line: &str, options: &QueryOptions) -> anyhow::Result<()> {
+    context.reload_sessions()?;
+    if context.sessions.active_session.is_none() {
+        let new_id = context.sessions.create_session();
+        println!("🆕 Created new session: {}", new_id);
+    }
+    context.ask(line, options).await?;
+    Ok(())
+}
+
+fn parse_format(format: &str) -> anyhow::Result<String> {
+    if !FORMATS.contains(&format) {
+        anyhow::bail!("Unknown format '{}'. Expected one of: {}", format, FORMATS.join(", "));
+    }
+    Ok(format.to_string())
+}
@@ -1,4 +1,6 @@
 use crate::config;
+use crate::llm::HistoryPolicy;
+use crate::retry::RetryPolicy;
 use std::fs;
 
 pub fn handle_config(set_api_key: Option<String>, show: bool) -> anyhow::Result<()> {
@@ -28,13 +30,48 @@ pub fn handle_config(set_api_key: Option<String>, show: bool) -> anyhow::Result<
     // Show config if requested
     if show {
         println!("Configuration file: {}", config_path.display());
+        println!("Vector store: {}", config.vector_store());
         println!("Chroma host: {}", config.chroma_host);
-        println!("OpenAI API key: {}", match config.openai_api_key {
+        println!(
+            "Chroma tenant/database/collection: {}/{}/{}",
+            config.chroma_tenant(),
+            config.chroma_database(),
+            config.chroma_collection()
+        );
+        println!("Chroma auth: {}", match (&config.chroma_auth_token, &config.chroma_username) {
+            (Some(_), _) => format!("Token via {} header (hidden for security)", config.chroma_auth_header()),
+            (None, Some(username)) => format!("Basic auth as {}", username),
+            (None, None) => "None".to_string(),
+        });
+        println!("OpenAI API key: {}", match &config.openai_api_key {
             Some(key) if !key.is_empty() => "Set (hidden for security)",
             _ => "Not set"
         });
         println!("OpenAI Completion Model: {}", config.openai_completion_model);
         println!("OpenAI Embedding Model: {}", config.openai_embedding_model);
+        println!("Embedding Provider: {}", config.embedding_provider());
+        println!("Embedding Model: {}", config.embedding_model());
+        if let Some(base_url) = &config.embedding_base_url {
+            println!("Embedding Base URL: {}", base_url);
+        }
+        println!("LLM Provider: {}", config.llm_provider());
+        println!("LLM Model: {}", config.llm_model());
+        if let Some(base_url) = &config.llm_base_url {
+            println!("LLM Base URL: {}", base_url);
+        }
+        let retry = RetryPolicy::from_config(&config);
+        println!(
+            "Retries: {} attempts, {}ms base delay{}",
+            retry.max_attempts,
+            retry.base_delay.as_millis(),
+            if retry.jitter { " with jitter" } else { "" }
+        );
+        let history = HistoryPolicy::from_config(&config);
+        println!(
+            "Session history: last {} Q&A pairs verbatim, up to {} tokens; older ones summarized",
+            history.max_turns,
+            history.token_budget
+        );
         println!("Supported Extensions: {:?}", config.file_extensions.unwrap());
         println!("Syntax Theme: {:?}", config.syntax_theme.unwrap());
         // Check environment variable
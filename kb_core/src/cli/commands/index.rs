@@ -1,112 +1,727 @@
-use crate::chroma;
+use crate::chunking::{self, Chunk};
 use crate::config;
-use crate::embedding;
+use crate::embedding::{self, EmbeddingInfo, EmbeddingProvider, RejectedRequest};
+use crate::project::Project;
+use crate::store::{self, VectorRecord, VectorStore};
 use crate::utils;
-use crate::state::{IndexState, IndexedChunk};
-use futures::stream::{FuturesUnordered, StreamExt};
-use std::time::{Duration, UNIX_EPOCH};
-use tokio::time::sleep;
+use crate::state::{IndexState, IndexedChunk, QueryState, StateLock};
+use futures::stream::{self, StreamExt};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use indicatif::{ProgressBar, ProgressStyle};
+use notify::{RecursiveMode, Watcher};
 use reqwest::Client;
 use std::fs;
-use std::path::Path;
-use uuid::Uuid;
+use std::path::{Path, PathBuf};
 
-const BATCH_SIZE: usize = 8;
+/// Upper bound on estimated tokens sent in a single embedding request.
+const MAX_BATCH_TOKENS: usize = 50_000;
+/// Estimated tokens of a chunk's text sent for embedding; longer text is cut,
+/// staying under the input limit of common embedding models.
+const MAX_CHUNK_TOKENS: usize = 8_000;
+const MAX_BATCH_ITEMS: usize = 256;
+const CONCURRENT_BATCHES: usize = 4;
+/// Quiet period after the last filesystem event before a watch pass starts.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// How often a long run saves its progress, so an interrupted run resumes.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
 
-pub async fn handle_index(client: &Client, path: &Path) -> anyhow::Result<()> {
+/// A changed file whose new chunks are waiting to be embedded.
+struct PendingFile {
+    file_str: String,
+    modified: u64,
+    /// Chunks whose content is unchanged, with their current ranges.
+    kept: Vec<IndexedChunk>,
+    /// IDs and updated metadata of the kept chunks.
+    refreshed: Vec<(String, serde_json::Value)>,
+    /// IDs of the previous version's chunks that no longer exist.
+    removed: Vec<String>,
+    new_chunks: Vec<IndexedChunk>,
+    remaining: usize,
+    failed: usize,
+}
+
+struct PendingChunk {
+    id: String,
+    file: usize,
+    source: String,
+    modified: u64,
+    chunk: Chunk,
+    text: String,
+    hash: String,
+}
+
+pub async fn handle_index(
+    client: &Client,
+    path: &Path,
+    watch: bool,
+    project: Option<String>,
+) -> anyhow::Result<()> {
+    let project = Project::resolve(project.as_deref(), path)?;
+    let state_dir = project.create_state_dir()?;
+    let _lock = StateLock::index(&state_dir)?;
+    let mut state = IndexState::load(&state_dir)?;
+    let app_config = config::load_config()?;
+    let embedder = embedding::from_config(client, &app_config)?;
+    let embedder = embedder.as_ref();
+    let collection = project.collection(&app_config);
+    let store = store::from_config(&app_config, &collection)?;
+    let store = store.as_ref();
+    let embedding = EmbeddingInfo::probe(embedder).await?;
+    store.ensure_collection(&embedding).await?;
+    store::check_embedding(store, &embedding).await?;
+    project.register(&app_config)?;
+    println!("📁 Project: {}", project.name);
+
+    if state.files.is_empty() {
+        state = IndexState::from_records(&store.list().await?);
+        if !state.files.is_empty() {
+            println!("♻️ Rebuilt index state for {} files from the vector store", state.files.len());
+        }
+    }
+
+    normalize_paths(&mut state, &project, store).await?;
+
+    let pb = spinner();
     let paths = utils::collect_files(path)?;
-    let total_files = paths.len() as u64;
-    let pb = ProgressBar::new(total_files);
+    let orphaned = orphaned_files(&state, &project, path, &paths)?;
+    let report = index_files(&paths, orphaned, &mut state, &project, &collection, embedder, store, &pb).await?;
+
+    if !watch {
+        report.finish(&pb)?;
+        return Ok(());
+    }
+
+    // Keep watching even if some chunks failed; they're retried on the next change
+    if let Err(e) = report.finish(&pb) {
+        eprintln!("❌ {}", e);
+    }
+    watch_path(path, &project, &collection, &mut state, embedder, store).await
+}
+
+/// Re-embeds every indexed file with `model`, then keeps it as the configured
+/// embedding model.
+///
+/// The collection is emptied first, so searches return nothing until the run
+/// finishes. An interrupted run resumes with `kb index`.
+pub async fn handle_reindex(client: &Client, model: &str, project: Option<String>) -> anyhow::Result<()> {
+    let project = Project::resolve(project.as_deref(), &std::env::current_dir()?)?;
+    let state_dir = project.state_dir()?;
+    let _lock = StateLock::index(&state_dir)?;
+    let mut state = IndexState::load(&state_dir)?;
+    let mut app_config = config::load_config()?;
+    app_config.embedding_model = Some(model.to_string());
+
+    // Fail on a bad model name with one embedding call before anything is deleted
+    let embedder = embedding::from_config(client, &app_config)?;
+    let embedder = embedder.as_ref();
+    let embedding = EmbeddingInfo::probe(embedder).await?;
+    let collection = project.collection(&app_config);
+    let store = store::from_config(&app_config, &collection)?;
+    let store = store.as_ref();
+
+    if state.files.is_empty() {
+        if let Ok(records) = store.list().await {
+            state = IndexState::from_records(&records);
+        }
+    }
+    normalize_paths(&mut state, &project, store).await?;
+    let (existing, missing): (Vec<String>, Vec<String>) =
+        state.files.keys().cloned().partition(|file| project.file_path(file).exists());
+    if existing.is_empty() {
+        anyhow::bail!(
+            "None of the indexed files of project '{}' exist any more. Run `kb index` to index it again",
+            project.name
+        );
+    }
+    if !missing.is_empty() {
+        println!("⚠️ Skipping {} indexed files that no longer exist", missing.len());
+    }
+
+    println!(
+        "🔁 Re-embedding {} files of project {} with {} ({} dimensions)",
+        existing.len(),
+        project.name,
+        embedding.model,
+        embedding.dimension
+    );
+
+    // Keep the files, without chunks, so an interrupted run can be started again
+    store.delete_collection().await?;
+    for file in &missing {
+        state.remove_file(file);
+    }
+    for file in &existing {
+        state.update_file_chunks(file, Vec::new(), 0);
+    }
+    state.save(&state_dir)?;
+    {
+        let _lock = StateLock::sessions(&state_dir)?;
+        QueryState::clear(&state_dir)?;
+    }
+    store.ensure_collection(&embedding).await?;
+
+    let paths: Vec<PathBuf> = existing.iter().map(|file| project.file_path(file)).collect();
+    let pb = spinner();
+    let report = index_files(&paths, Vec::new(), &mut state, &project, &collection, embedder, store, &pb).await?;
+
+    // The collection holds the new model's vectors now, so `kb index` must use it
+    // to retry any failed chunks
+    let config_path = config::set_config_value("embedding_model", model)?;
+    let result = report.finish(&pb);
+    println!("✅ Set embedding_model = \"{}\" in {}", model, config_path.display());
+    result
+}
+
+/// Result of an indexing pass.
+struct IndexReport {
+    total: usize,
+    failures: Vec<(String, String)>,
+    removed_files: usize,
+    moved_chunks: usize,
+}
+
+impl IndexReport {
+    /// Finishes the progress bar and lists chunks that couldn't be indexed.
+    fn finish(&self, pb: &ProgressBar) -> anyhow::Result<()> {
+        if self.removed_files > 0 {
+            pb.println(format!("🗑️ Removed {} deleted or moved files from the index", self.removed_files));
+        }
+        if self.moved_chunks > 0 {
+            pb.println(format!("🚚 Kept {} chunks from renamed files without re-embedding", self.moved_chunks));
+        }
+
+        if self.failures.is_empty() {
+            pb.finish_with_message("🎉 Indexing complete.");
+            return Ok(());
+        }
+
+        pb.finish_with_message(format!("⚠️ Indexing finished with {} failed chunks.", self.failures.len()));
+        eprintln!("\n❌ These chunks could not be indexed and will be retried on the next run:");
+        for (location, error) in &self.failures {
+            eprintln!("  {} — {}", location, error);
+        }
+        anyhow::bail!("{} of {} chunks failed to index", self.failures.len(), self.total)
+    }
+}
+
+fn spinner() -> ProgressBar {
+    let pb = ProgressBar::new_spinner();
     pb.set_style(
         ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] {msg}")
             .unwrap()
             .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ "),
     );
+    pb
+}
+
+/// Indexed files under `root` that the walk no longer finds.
+fn orphaned_files(state: &IndexState, project: &Project, root: &Path, walked: &[PathBuf]) -> anyhow::Result<Vec<String>> {
+    let root = project.index_path(root)?;
+    let walked: HashSet<String> = walked.iter().map(|p| project.index_path(p)).collect::<anyhow::Result<_>>()?;
+    Ok(state
+        .files
+        .keys()
+        .filter(|f| Path::new(f).starts_with(&root) && !walked.contains(*f))
+        .cloned()
+        .collect())
+}
+
+/// Moves files recorded under another form of their path, such as relative to
+/// the directory an older `kb index` ran from, to `Project::index_path`'s.
+///
+/// Their chunks keep their vectors. The files get a zero timestamp so the next
+/// pass over them updates the chunks' source; entries duplicating a file that's
+/// already recorded are dropped with their chunks.
+async fn normalize_paths(state: &mut IndexState, project: &Project, store: &dyn VectorStore) -> anyhow::Result<()> {
+    let cwd = std::env::current_dir()?;
+    let mut moved = 0;
+    let mut duplicates: Vec<String> = Vec::new();
+
+    for file in state.files.keys().cloned().collect::<Vec<_>>() {
+        let Some(found) = [project.file_path(&file), cwd.join(&file)].into_iter().find(|p| p.exists()) else {
+            continue;
+        };
+        let indexed = project.index_path(&found)?;
+        if indexed == file {
+            continue;
+        }
+
+        let Some(meta) = state.remove_file(&file) else { continue };
+        if state.files.contains_key(&indexed) {
+            duplicates.extend(meta.chunks.into_iter().map(|c| c.id));
+        } else {
+            state.update_file_chunks(&indexed, meta.chunks, 0);
+            moved += 1;
+        }
+    }
+
+    if !duplicates.is_empty() {
+        store.delete(&duplicates).await?;
+    }
+    if moved > 0 || !duplicates.is_empty() {
+        println!("🔧 Updated {} indexed paths to the project's form", moved + duplicates.len());
+    }
+    Ok(())
+}
+
+/// Embeds the chunks of `paths` that changed since the last run, records them
+/// in `state` and saves it, checkpointing periodically along the way.
+///
+/// `orphaned` files are dropped from the index. Their chunks are first offered
+/// to the new files by content hash, so a renamed file keeps its vectors and
+/// only needs a metadata update; chunks nobody claims are deleted. New chunk
+/// IDs are derived from `collection`, the project's collection name.
+#[allow(clippy::too_many_arguments)]
+async fn index_files(
+    paths: &[PathBuf],
+    orphaned: Vec<String>,
+    state: &mut IndexState,
+    project: &Project,
+    collection: &str,
+    embedder: &dyn EmbeddingProvider,
+    store: &dyn VectorStore,
+    pb: &ProgressBar,
+) -> anyhow::Result<IndexReport> {
+    let state_dir = project.state_dir()?;
+    let mut files: Vec<PendingFile> = Vec::new();
+    let mut pending: Vec<PendingChunk> = Vec::new();
 
-    let config_dir = config::get_config_dir()?;
-    let mut state = IndexState::load(&config_dir)?;
+    let removed_files = orphaned.len();
+    let mut moved: HashMap<String, Vec<IndexedChunk>> = HashMap::new();
+    for file in orphaned {
+        for chunk in state.remove_file(&file).map(|meta| meta.chunks).unwrap_or_default() {
+            moved.entry(chunk.hash.clone()).or_default().push(chunk);
+        }
+    }
+    let mut moved_chunks = 0;
 
     for path in paths {
-        pb.set_message(format!("Indexing {}", path.display()));
-        let metadata = fs::metadata(&path)?;
+        pb.set_message(format!("Scanning {}", path.display()));
+        let metadata = fs::metadata(path)?;
         let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
-        let file_str = path.to_string_lossy().to_string();
+        let file_str = project.index_path(path)?;
 
         // Skip if file unchanged
         if let Some(prev) = state.get_last_modified(&file_str) {
             if prev == modified {
-                pb.inc(1);
                 continue;
             }
         }
 
-        let content = fs::read_to_string(&path)?;
-        let chunks = utils::chunk_text(&content);
-        let prev_chunks = state.get_file_chunks(&file_str).cloned().unwrap_or_default();
-        let mut new_chunks = Vec::new();
-        let mut chunk_info = Vec::new();
+        let content = fs::read_to_string(path)?;
+        let chunks = chunking::chunk_file(path, &content);
+        let file = files.len();
+        let first_pending = pending.len();
 
-        for chunk in &chunks {
-            if chunk.trim().is_empty() || chunk.len() > 100_000 {
-                continue;
-            }
+        let mut previous: HashMap<String, Vec<IndexedChunk>> = HashMap::new();
+        for chunk in state.get_file_chunks(&file_str).cloned().unwrap_or_default() {
+            previous.entry(chunk.hash.clone()).or_default().push(chunk);
+        }
+        let mut kept: Vec<IndexedChunk> = Vec::new();
+        let mut refreshed: Vec<(String, serde_json::Value)> = Vec::new();
 
-            let hash = IndexState::hash_chunk(chunk);
-            if IndexState::has_chunk(&prev_chunks, &hash) {
+        for chunk in chunks {
+            if chunk.content.trim().is_empty() || chunk.content.len() > 100_000 {
                 continue;
             }
 
-            chunk_info.push((chunk.clone(), hash));
+            let mut text = chunk.embedding_text();
+            let hash = IndexState::hash_chunk(&text);
+            text.truncate(utils::truncated_len(&text, MAX_CHUNK_TOKENS));
+
+            // Unchanged content keeps its vector, from this file or a renamed one
+            let (old, renamed) = match previous.get_mut(&hash).and_then(Vec::pop) {
+                Some(old) => (old, false),
+                None => match moved.get_mut(&hash).and_then(Vec::pop) {
+                    Some(old) => (old, true),
+                    None => {
+                        pending.push(PendingChunk { id: String::new(), file, source: file_str.clone(), modified, chunk, text, hash });
+                        continue;
+                    }
+                },
+            };
+
+            // The file's modification time changed, so refresh the metadata
+            // even when the chunk's location didn't
+            refreshed.push((old.id.clone(), store::chunk_metadata(&chunk, &file_str, &hash, modified)));
+            moved_chunks += renamed as usize;
+            kept.push(IndexedChunk {
+                id: old.id,
+                hash,
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                start_byte: chunk.start_byte,
+                end_byte: chunk.end_byte,
+            });
         }
 
-        for batch in chunk_info.chunks(BATCH_SIZE) {
-            let mut tasks = FuturesUnordered::new();
+        // Number duplicate chunks past the IDs this file already uses
+        let mut used: HashSet<String> = kept.iter().map(|c| c.id.clone()).collect();
+        for p in &mut pending[first_pending..] {
+            p.id = (0..)
+                .map(|n| IndexState::chunk_id(collection, &file_str, &p.hash, n))
+                .find(|id| !used.contains(id))
+                .unwrap();
+            used.insert(p.id.clone());
+        }
+
+        files.push(PendingFile {
+            file_str,
+            modified,
+            kept,
+            refreshed,
+            removed: previous.into_values().flatten().map(|c| c.id).collect(),
+            new_chunks: Vec::new(),
+            remaining: pending.len() - first_pending,
+            failed: 0,
+        });
+
+        if pending.len() == first_pending {
+            finish_file(&mut files[file], state, store).await?;
+        }
+    }
+
+    // Every rename has been claimed by now; drop the rest before any checkpoint
+    // forgets which files they belonged to
+    let unclaimed: Vec<String> = moved.into_values().flatten().map(|c| c.id).collect();
+    if !unclaimed.is_empty() {
+        store.delete(&unclaimed).await?;
+    }
 
-            for (chunk, hash) in batch.iter().cloned() {
-                let client = client.clone();
-                let path = path.to_path_buf();
-                let pb = pb.clone();
-                tasks.push(async move {
-                    sleep(Duration::from_millis(100)).await;
-                    let embedding = embedding::get_embedding(&client, &chunk).await?;
-                    let id = Uuid::new_v4().to_string();
-                    chroma::send_to_chroma(&client, &id, &chunk, &embedding, &path, &pb).await?;
-                    Ok::<_, anyhow::Error>(IndexedChunk { id, hash })
-                });
+    let total = pending.len();
+    let mut done = 0;
+    let mut failures: Vec<(String, String)> = Vec::new();
+    let mut last_checkpoint = Instant::now();
+    let mut results = stream::iter(token_batches(&pending))
+        .map(|range| {
+            let batch = &pending[range.clone()];
+            async move { (range, embed_batch(embedder, store, batch).await) }
+        })
+        .buffer_unordered(CONCURRENT_BATCHES);
+
+    while let Some((range, embedded)) = results.next().await {
+        for (part, result) in embedded {
+            let batch = &pending[range.start + part.start..range.start + part.end];
+            match result {
+                Ok(ids) => {
+                    for (p, id) in batch.iter().zip(ids) {
+                        files[p.file].new_chunks.push(IndexedChunk {
+                            id,
+                            hash: p.hash.clone(),
+                            start_line: p.chunk.start_line,
+                            end_line: p.chunk.end_line,
+                            start_byte: p.chunk.start_byte,
+                            end_byte: p.chunk.end_byte,
+                        });
+                    }
+                }
+                Err(e) => {
+                    pb.println(format!("❌ Failed to index {} chunks: {}", batch.len(), e));
+                    for p in batch {
+                        files[p.file].failed += 1;
+                        let location = format!("{}:{}-{}", p.source, p.chunk.start_line, p.chunk.end_line);
+                        failures.push((location, e.to_string()));
+                    }
+                }
             }
 
-            while let Some(result) = tasks.next().await {
-                if let Ok(chunk) = result {
-                    new_chunks.push(chunk);
+            done += batch.len();
+            pb.set_message(format!("✅ Indexed {}/{} chunks", done, total));
+
+            for p in batch {
+                let file = &mut files[p.file];
+                file.remaining -= 1;
+                if file.remaining == 0 {
+                    finish_file(file, state, store).await?;
                 }
             }
         }
 
-        if !new_chunks.is_empty() {
-            let mut updated_chunks = prev_chunks.clone();
-            let mut removed_chunks = Vec::new();
+        if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            store.flush().await?;
+            state.save(&state_dir)?;
+            last_checkpoint = Instant::now();
+        }
+    }
 
-            updated_chunks.retain(|c| {
-                let keep = new_chunks.iter().all(|n| n.hash != c.hash);
-                if !keep {
-                    removed_chunks.push(c.clone());
-                }
-                keep
-            });
+    store.flush().await?;
+    state.save(&state_dir)?;
+
+    Ok(IndexReport { total, failures, removed_files, moved_chunks })
+}
+
+/// Re-indexes files under `root` as they change until interrupted.
+///
+/// Bursts of events are debounced, then the changed paths are filtered through
+/// the same walk as a full index so `.kbignore` and `global.kbignore` apply.
+async fn watch_path(
+    root: &Path,
+    project: &Project,
+    collection: &str,
+    state: &mut IndexState,
+    embedder: &dyn EmbeddingProvider,
+    store: &dyn VectorStore,
+) -> anyhow::Result<()> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // Reading files while indexing raises access events; only changes matter
+        if let Ok(event) = event {
+            if !event.kind.is_access() {
+                let _ = tx.send(event);
+            }
+        }
+    })?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+
+    // Events carry absolute paths, while the state is keyed by paths as walked from `root`
+    let canonical_root = root.canonicalize()?;
+    let to_root = |p: &Path| match p.strip_prefix(&canonical_root) {
+        Ok(rel) => root.join(rel),
+        Err(_) => p.to_path_buf(),
+    };
+
+    println!("👀 Watching {} for changes (Ctrl-C to stop)", root.display());
+
+    // Paths of a pass that failed, tried again with the next change. Files it
+    // checkpointed are skipped then.
+    let mut retry: BTreeSet<PathBuf> = BTreeSet::new();
+
+    loop {
+        let mut changed = std::mem::take(&mut retry);
+
+        let event = tokio::select! {
+            event = rx.recv() => event,
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(event) = event else { break };
+        changed.extend(event.paths.iter().map(|p| to_root(p)));
 
-            updated_chunks.extend(new_chunks);
-            state.update_file_chunks(&file_str, updated_chunks, modified);
+        // Wait for the burst to settle
+        while let Ok(Some(event)) = tokio::time::timeout(WATCH_DEBOUNCE, rx.recv()).await {
+            changed.extend(event.paths.iter().map(|p| to_root(p)));
+        }
 
-            for chunk in removed_chunks {
-                chroma::delete_chunk(client, &chunk.id).await?;
+        let scanned = utils::collect_files(root)
+            .and_then(|indexable| Ok((orphaned_files(state, project, root, &indexable)?, indexable)));
+        let (orphaned, indexable) = match scanned {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                eprintln!("❌ Failed to scan {}: {:#}", root.display(), e);
+                retry = changed;
+                continue;
             }
+        };
+        let modified: Vec<PathBuf> = indexable
+            .into_iter()
+            .filter(|p| changed.contains(p))
+            .collect();
+
+        if modified.is_empty() && orphaned.is_empty() {
+            continue;
         }
 
-        pb.inc(1);
+        let pb = spinner();
+        let report = match index_files(&modified, orphaned, state, project, collection, embedder, store, &pb).await {
+            Ok(report) => report,
+            Err(e) => {
+                pb.finish_and_clear();
+                eprintln!("❌ Indexing failed, retrying with the next change: {:#}", e);
+                // Back to the last saved state, so files the pass dropped are orphaned again
+                *state = IndexState::load(&project.state_dir()?)?;
+                retry = modified.into_iter().collect();
+                continue;
+            }
+        };
+
+        if let Err(e) = report.finish(&pb) {
+            eprintln!("❌ {}", e);
+        }
     }
 
-    state.save(&config_dir)?;
-    pb.finish_with_message("🎉 Indexing complete.");
+    store.flush().await?;
+    state.save(&project.state_dir()?)?;
     Ok(())
 }
+
+/// Splits pending chunks into consecutive ranges that fit in one embedding request.
+fn token_batches(pending: &[PendingChunk]) -> Vec<Range<usize>> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+
+    for (i, p) in pending.iter().enumerate() {
+        let chunk_tokens = utils::estimate_tokens(&p.text);
+        if i > start && (tokens + chunk_tokens > MAX_BATCH_TOKENS || i - start >= MAX_BATCH_ITEMS) {
+            batches.push(start..i);
+            start = i;
+            tokens = 0;
+        }
+        tokens += chunk_tokens;
+    }
+
+    if start < pending.len() {
+        batches.push(start..pending.len());
+    }
+
+    batches
+}
+
+/// Embeds a batch and writes it to the store. A batch the provider rejects is
+/// split in half and retried, so only the chunks rejected on their own fail.
+///
+/// Returns the outcome of each part of the batch, as ranges into it.
+async fn embed_batch(
+    embedder: &dyn EmbeddingProvider,
+    store: &dyn VectorStore,
+    batch: &[PendingChunk],
+) -> Vec<(Range<usize>, anyhow::Result<Vec<String>>)> {
+    let mut results = Vec::new();
+    let mut parts = Vec::new();
+    parts.push(0..batch.len());
+
+    while let Some(part) = parts.pop() {
+        match embed_part(embedder, store, &batch[part.clone()]).await {
+            Err(e) if part.len() > 1 && RejectedRequest::is(&e) => {
+                let middle = part.start + part.len() / 2;
+                parts.push(middle..part.end);
+                parts.push(part.start..middle);
+            }
+            result => results.push((part, result)),
+        }
+    }
+
+    results
+}
+
+/// Embeds chunks with one request and writes them with one store call,
+/// returning the new chunk IDs in order.
+async fn embed_part(
+    embedder: &dyn EmbeddingProvider,
+    store: &dyn VectorStore,
+    chunks: &[PendingChunk],
+) -> anyhow::Result<Vec<String>> {
+    let texts: Vec<String> = chunks.iter().map(|p| p.text.clone()).collect();
+    let embeddings = embedder.embed(&texts).await?;
+
+    let records: Vec<VectorRecord> = chunks
+        .iter()
+        .zip(embeddings)
+        .map(|(p, embedding)| VectorRecord {
+            id: p.id.clone(),
+            document: p.chunk.content.clone(),
+            embedding,
+            metadata: store::chunk_metadata(&p.chunk, &p.source, &p.hash, p.modified),
+        })
+        .collect();
+
+    store.upsert(&records).await?;
+    Ok(records.into_iter().map(|r| r.id).collect())
+}
+
+/// Applies a file's chunk diff to the store, then records it in the state.
+async fn finish_file(
+    file: &mut PendingFile,
+    state: &mut IndexState,
+    store: &dyn VectorStore,
+) -> anyhow::Result<()> {
+    if !file.refreshed.is_empty() {
+        let (ids, metadatas): (Vec<String>, Vec<serde_json::Value>) =
+            std::mem::take(&mut file.refreshed).into_iter().unzip();
+        store.update_metadata(&ids, &metadatas).await?;
+    }
+
+    if !file.removed.is_empty() {
+        store.delete(&file.removed).await?;
+    }
+
+    let mut chunks = std::mem::take(&mut file.kept);
+    chunks.extend(std::mem::take(&mut file.new_chunks));
+
+    // A zero timestamp never matches the file, so chunks that failed are retried next run
+    let modified = if file.failed > 0 { 0 } else { file.modified };
+    state.update_file_chunks(&file.file_str, chunks, modified);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::MockEmbedder;
+    use crate::store::LocalStore;
+    use async_trait::async_trait;
+    use reqwest::StatusCode;
+
+    /// Rejects any request containing a text with "oversized" in it.
+    struct PickyEmbedder(MockEmbedder);
+
+    #[async_trait]
+    impl EmbeddingProvider for PickyEmbedder {
+        async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+            if texts.iter().any(|t| t.contains("oversized")) {
+                return Err(RejectedRequest { status: StatusCode::BAD_REQUEST, body: "too long".to_string() }.into());
+            }
+            self.0.embed(texts).await
+        }
+
+        fn model(&self) -> &str {
+            "picky"
+        }
+    }
+
+    fn pending(texts: &[&str]) -> Vec<PendingChunk> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| PendingChunk {
+                id: format!("chunk-{}", i),
+                file: 0,
+                source: "src/lib.rs".to_string(),
+                modified: 0,
+                chunk: Chunk {
+                    content: text.to_string(),
+                    breadcrumb: None,
+                    start_line: i + 1,
+                    end_line: i + 1,
+                    start_byte: 0,
+                    end_byte: text.len(),
+                },
+                text: text.to_string(),
+                hash: IndexState::hash_chunk(text),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rejected_batches_fail_only_the_rejected_chunks() {
+        let embedder = PickyEmbedder(MockEmbedder::new(8));
+        let store = LocalStore::open(&std::env::temp_dir().join(format!("kb-embed-batch-{}", uuid::Uuid::new_v4()))).unwrap();
+        let batch = pending(&["fn a()", "fn b()", "oversized", "fn c()", "fn d()"]);
+
+        let mut results = embed_batch(&embedder, &store, &batch).await;
+        results.sort_by_key(|(part, _)| part.start);
+
+        let failed: Vec<(usize, usize)> = results.iter().filter(|(_, r)| r.is_err()).map(|(p, _)| (p.start, p.end)).collect();
+        assert_eq!(failed, [(2, 3)]);
+        assert_eq!(results.iter().map(|(p, _)| p.len()).sum::<usize>(), batch.len());
+        assert_eq!(store.list().await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn other_errors_fail_the_whole_batch() {
+        let store = LocalStore::open(&std::env::temp_dir().join(format!("kb-embed-batch-{}", uuid::Uuid::new_v4()))).unwrap();
+        store.upsert(&[VectorRecord {
+            id: "existing".to_string(),
+            document: String::new(),
+            embedding: vec![1.0; 4],
+            metadata: serde_json::json!({}),
+        }]).await.unwrap();
+        let batch = pending(&["fn a()", "fn b()"]);
+
+        let results = embed_batch(&MockEmbedder::new(8), &store, &batch).await;
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_err());
+    }
+}
@@ -1,4 +1,7 @@
+pub mod browse;
+pub mod chat;
 pub mod config;
 pub mod index;
+pub mod projects;
 pub mod query;
 pub mod session;
@@ -0,0 +1,42 @@
+use crate::config;
+use crate::project::Project;
+use crate::state::StateLock;
+use crate::store;
+
+pub fn handle_list() -> anyhow::Result<()> {
+    let projects = Project::list()?;
+    if projects.is_empty() {
+        println!("No projects indexed yet");
+        return Ok(());
+    }
+
+    println!("📁 Projects:");
+    for (name, info) in projects {
+        let root = info
+            .root
+            .map(|r| r.display().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!("  {} ({}) → {}", name, root, info.collection);
+    }
+
+    Ok(())
+}
+
+pub async fn handle_remove(name: &str) -> anyhow::Result<()> {
+    let project = Project::named(name)?;
+    if !Project::list()?.iter().any(|(n, _)| n == name) {
+        anyhow::bail!("No project named '{}'. Run `kb projects list` to see indexed projects", name);
+    }
+
+    let state_dir = project.state_dir()?;
+    {
+        let _lock = StateLock::index(&state_dir)?;
+        let app_config = config::load_config()?;
+        let store = store::from_config(&app_config, &project.collection(&app_config))?;
+        store.delete_collection().await?;
+    }
+    project.remove_state()?;
+
+    println!("🗑️ Removed project: {}", name);
+    Ok(())
+}
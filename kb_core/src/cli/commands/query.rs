@@ -1,168 +1,488 @@
-use crate::chroma::{self, SearchResult};
-use crate::embedding;
-use crate::llm;
-use crate::utils;
+use crate::config::{self, AppConfig};
+use crate::embedding::{self, EmbeddingInfo, EmbeddingProvider};
+use crate::llm::{self, citations::check_citations, HistoryPolicy, LlmProvider};
+use crate::project::Project;
+use crate::search::{self, LexicalResults, SearchMode};
+use crate::cli::FilterArgs;
+use crate::store::{self, MetadataFilter, QueryHit, SearchResult, VectorStore};
+use crate::utils::{self, MarkdownStream};
+use anyhow::Context;
 use reqwest::Client;
-use std::path::Path;
-use crate::state::{QueryState, SessionManager, hash_query_context};
-use crate::config;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::state::{IndexState, QueryState, SessionManager, StateLock, hash_query_context};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_query(
     client: &Client,
     query: &str,
     top_k: usize,
     format: &str,
+    mode: &str,
     session_id: Option<String>,
+    project: Option<String>,
+    filters: &FilterArgs,
 ) -> anyhow::Result<()> {
-    let config_dir = config::get_config_dir()?;
-    let mut cache = QueryState::load(&config_dir)?;
-    let mut session_manager = SessionManager::load(&config_dir)?;
+    let options = QueryOptions {
+        top_k,
+        format: format.to_string(),
+        mode: SearchMode::parse(mode)?,
+        filters: filters.clone(),
+    };
+    let project = Project::resolve(project.as_deref(), &std::env::current_dir()?)?;
+    let _lock = StateLock::sessions(&project.state_dir()?)?;
+    let mut context = QueryContext::load(client, project)?;
 
     // Handle session management
     if let Some(id) = session_id {
         if id == "new" {
-            let new_id = session_manager.create_session();
+            let new_id = context.sessions.create_session();
             println!("🆕 Created new session: {}", new_id);
         } else {
             // Pass a reference to set_active_session
-            session_manager.set_active_session(&id)?;
+            context.sessions.set_active_session(&id)?;
             println!("🔄 Switched to session: {}", id);
         }
-    } else if session_manager.active_session.is_none() {
+    } else if context.sessions.active_session.is_none() {
         // Create a default session if none exists
-        let new_id = session_manager.create_session();
+        let new_id = context.sessions.create_session();
         println!("🆕 Created default session: {}", new_id);
     }
 
+    let answered = context.ask(query, &options).await?;
 
-    // Embed the query
-    let query_embedding = embedding::get_embedding(client, query).await?;
+    if answered {
+        if let Some(session) = context.sessions.get_active_session() {
+            println!("\n📝 Session: {} (Q&A: {})",
+                &session.id[..8],
+                session.queries.len()
+            );
+        }
+    }
 
-    // 🔍 Try similarity cache
-    if let Some(similar) = cache.find_similar(&query_embedding, 0.93) {
-        println!("💡 Cached Answer:\n\n{}", utils::render_markdown_highlighted(&similar));
+    Ok(())
+}
 
-        // Add to session history even if cached
-        if let Some(session) = session_manager.get_active_session_mut() {
-            session.queries.push(query.to_string());
-            session.responses.push(similar);
-            session_manager.save(&config_dir)?;
-        }
+/// How to search for and present the answer to a question.
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    pub top_k: usize,
+    /// Output format: pretty, json, markdown or smart.
+    pub format: String,
+    pub mode: SearchMode,
+    pub filters: FilterArgs,
+}
 
-        return Ok(());
-    }
-
-    // Otherwise: do Chroma vector search
-    let parsed = chroma::query_chroma(client, &query_embedding, top_k).await?;
-
-    let docs = parsed["documents"]
-        .as_array()
-        .and_then(|outer| outer.get(0))
-        .and_then(|inner| inner.as_array())
-        .ok_or_else(|| anyhow::anyhow!("No documents in response"))?;
-
-    let metas = parsed["metadatas"]
-        .as_array()
-        .and_then(|outer| outer.get(0))
-        .and_then(|inner| inner.as_array())
-        .ok_or_else(|| anyhow::anyhow!("No metadatas in response"))?;
-
-    let dists = parsed["distances"]
-        .as_array()
-        .and_then(|outer| outer.get(0))
-        .and_then(|inner| inner.as_array())
-        .ok_or_else(|| anyhow::anyhow!("No distances in response"))?;
-
-    let results: Vec<SearchResult> = docs
-        .iter()
-        .enumerate()
-        .map(|(i, doc)| {
-            let text = doc.as_str().unwrap_or("<invalid UTF-8>");
-            let source = metas[i]
-                .get("source")
-                .and_then(|v| v.as_str())
-                .unwrap_or("<unknown>");
-            let distance = dists[i].as_f64().unwrap_or_default();
-
-            SearchResult {
-                index: i + 1,
-                source,
-                distance,
-                content: text,
-            }
+/// Configuration, clients and state that queries need. `kb query` loads one
+/// per run; `kb chat` keeps one for the whole conversation.
+pub struct QueryContext {
+    client: Client,
+    app_config: AppConfig,
+    project: Project,
+    pub config_dir: PathBuf,
+    pub cache: QueryState,
+    pub sessions: SessionManager,
+    embedder: Option<Box<dyn EmbeddingProvider>>,
+    store: Option<Box<dyn VectorStore>>,
+    llm: Option<Box<dyn LlmProvider>>,
+    history: HistoryPolicy,
+    /// Chunks retrieved for the last question.
+    pub last_hits: Vec<QueryHit>,
+    /// Indexed files the last search couldn't read.
+    pub missing_files: Vec<String>,
+}
+
+impl QueryContext {
+    pub fn load(client: &Client, project: Project) -> anyhow::Result<Self> {
+        let config_dir = project.state_dir()?;
+
+        let app_config = config::load_config()?;
+        Ok(QueryContext {
+            client: client.clone(),
+            history: HistoryPolicy::from_config(&app_config),
+            app_config,
+            cache: QueryState::open(&config_dir)?,
+            sessions: SessionManager::load(&config_dir)?,
+            project,
+            config_dir,
+            embedder: None,
+            store: None,
+            llm: None,
+            last_hits: Vec::new(),
+            missing_files: Vec::new(),
         })
-        .collect();
-
-    match format {
-        "json" => println!("{}", serde_json::to_string_pretty(&results)?),
-        "markdown" => {
-            for r in &results {
-                let lang = Path::new(r.source)
-                    .extension()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("text");
-                println!("### Result {}\n", r.index);
-                println!("**Source:** `{}`  ", r.source);
-                println!("**Distance:** `{:.4}`  ", r.distance);
-                println!("```{}\n{}\n```", lang, r.content);
-                println!();
+    }
+
+    /// Re-reads the sessions so a long-lived context doesn't overwrite turns
+    /// other `kb` processes added. Keeps this context's session active if it
+    /// still exists. The caller holds the sessions lock.
+    pub fn reload_sessions(&mut self) -> anyhow::Result<()> {
+        let active = self.sessions.active_session.take();
+        self.sessions = SessionManager::load(&self.config_dir)?;
+        if let Some(id) = active.filter(|id| self.sessions.active_session.as_ref() != Some(id)) {
+            if self.sessions.sessions.contains_key(&id) {
+                self.sessions.set_active_session(&id)?;
             }
         }
-        "smart" => {
-            let context_chunks: Vec<String> = results.iter()
-                .map(|r| {
+        Ok(())
+    }
+
+    /// Searches the index and prints the results or the LLM's answer in
+    /// `options.format`. Smart answers are cached and added to the active
+    /// session. Returns whether an answer was given.
+    ///
+    /// The caller holds the sessions lock.
+    pub async fn ask(&mut self, query: &str, options: &QueryOptions) -> anyhow::Result<bool> {
+        let query_embedding = self.embed_query(query, options.mode).await?;
+
+        // 🔍 Try similarity cache, which only holds answers to unfiltered queries
+        let scope = options.filters.is_empty().then(|| options.mode.name());
+        let similar = match (&query_embedding, scope) {
+            (Some(embedding), Some(scope)) => self.cache.find_similar(embedding, scope, 0.93)?,
+            _ => None,
+        };
+        if let Some(similar) = similar {
+            println!("💡 Cached Answer:\n\n{}", utils::render_markdown_highlighted(&similar.answer));
+            let results: Vec<SearchResult> = similar
+                .sources
+                .iter()
+                .enumerate()
+                .map(|(i, hit)| SearchResult::from_hit(i + 1, hit))
+                .collect();
+            print!("{}", sources_footer(&similar.answer, &results));
+
+            // Add to session history even if cached
+            if self.sessions.active_session.is_some() {
+                self.sessions.add_interaction(query.to_string(), similar.answer)?;
+                self.sessions.save(&self.config_dir)?;
+            }
+
+            self.last_hits = similar.sources;
+            return Ok(true);
+        }
+
+        // Otherwise: search the index
+        let Some(hits) = self.search(query, query_embedding.as_deref(), options).await? else {
+            println!("⚠️ No indexed files match --path {}", options.filters.paths.join(", "));
+            return Ok(false);
+        };
+        if let Some(warning) = self.missing_files_warning() {
+            println!("{}\n", warning);
+        }
+
+        let results: Vec<SearchResult> = hits
+            .iter()
+            .enumerate()
+            .map(|(i, hit)| SearchResult::from_hit(i + 1, hit))
+            .collect();
+
+        let answered = match options.format.as_str() {
+            "json" => {
+                println!("{}", serde_json::to_string_pretty(&results)?);
+                false
+            }
+            "markdown" => {
+                for r in &results {
                     let lang = Path::new(r.source)
                         .extension()
                         .and_then(|s| s.to_str())
                         .unwrap_or("text");
+                    println!("### Result {}\n", r.index);
+                    match r.breadcrumb {
+                        Some(breadcrumb) => println!("**Source:** `{}` — {}  ", r.location, breadcrumb),
+                        None => println!("**Source:** `{}`  ", r.location),
+                    }
+                    if let Some(distance) = r.distance {
+                        println!("**Distance:** `{:.4}`  ", distance);
+                    }
+                    if let Some(score) = r.score {
+                        println!("**Score:** `{:.4}`  ", score);
+                    }
+                    println!("```{}\n{}\n```", lang, r.content);
+                    println!();
+                }
+                false
+            }
+            "smart" => {
+                let context_chunks: Vec<String> = results.iter().map(context_chunk).collect();
 
-                    format!(
-                        "**File:** `{}`\n\n```{}\n{}\n```",
-                        r.source, lang, r.content
-                    )
-                })
-                .collect();
+                let context_hash = hash_query_context(query, &context_chunks);
+
+                let raw_answer = match self.cache.get_cached_answer(query, &context_hash)? {
+                    Some(cached) => {
+                        println!("💡 Answer:\n\n{}", utils::render_markdown_highlighted(&cached));
+                        cached
+                    }
+                    None => {
+                        // Print the answer as it's generated rather than after the whole completion
+                        println!("💡 Answer:\n");
+                        self.summarize_history().await?;
+                        let mut renderer = MarkdownStream::default();
+                        let answer = llm::stream_llm_response(
+                            self.llm.as_deref().unwrap(),
+                            query,
+                            &context_chunks,
+                            Some(&self.sessions),
+                            &self.history,
+                            &mut |token| {
+                                print!("{}", renderer.push(token));
+                                let _ = io::stdout().flush();
+                            },
+                        ).await?;
+                        println!("{}", renderer.finish());
+                        answer
+                    }
+                };
+                print!("{}", sources_footer(&raw_answer, &results));
 
-            let context_hash = hash_query_context(query, &context_chunks);
+                // 🧠 Cache the answer with the current query embedding; lexical
+                // searches have none to find it by later
+                if let Some(embedding) = query_embedding {
+                    self.cache.insert_answer(
+                        query.to_string(),
+                        context_hash,
+                        embedding,
+                        scope.map(str::to_string),
+                        raw_answer.clone(),
+                        hits.clone(),
+                    )?;
+                }
+
+                // Add to session history
+                self.sessions.add_interaction(query.to_string(), raw_answer)?;
+                self.sessions.save(&self.config_dir)?;
+                true
+            }
+            _ => {
+                print_results(&results);
+                false
+            }
+        };
+
+        self.last_hits = hits;
+        Ok(answered)
+    }
+
+    /// Embeds the query unless the search is purely lexical, checking that the
+    /// index was built with the same model.
+    pub async fn embed_query(&mut self, query: &str, mode: SearchMode) -> anyhow::Result<Option<Vec<f32>>> {
+        if !mode.uses_embeddings() {
+            return Ok(None);
+        }
+
+        let (embedder, store) = self.vector_search()?;
+        let query_embedding = embedder.embed_one(query).await?;
+        store::check_embedding(store, &EmbeddingInfo::of(embedder, &query_embedding)).await?;
+        Ok(Some(query_embedding))
+    }
 
-            // Pass session manager to get_llm_response
-            let raw_answer = llm::get_llm_response(
-                client,
-                query,
-                &context_chunks,
-                Some(&session_manager)
-            ).await?;
+    /// Searches the index with `options`' mode and filters. Returns `None` when
+    /// the path filter matches no indexed files.
+    pub async fn search(
+        &mut self,
+        query: &str,
+        query_embedding: Option<&[f32]>,
+        options: &QueryOptions,
+    ) -> anyhow::Result<Option<Vec<QueryHit>>> {
+        let mode = options.mode;
+        let filters = &options.filters;
 
-            let rendered = utils::render_markdown_highlighted(&raw_answer);
+        // Only lexical search needs the chunks; a path filter needs just the file list
+        let state = if mode != SearchMode::Semantic {
+            Some(IndexState::load(&self.config_dir)?)
+        } else {
+            None
+        };
+        let indexed = match &state {
+            Some(state) => state.files.keys().cloned().collect(),
+            None if filters.has_paths() => IndexState::indexed_paths(&self.config_dir)?,
+            None => Vec::new(),
+        };
+        let filter = search::build_filter(
+            &self.project,
+            &indexed,
+            &filters.paths,
+            &filters.exclude,
+            &filters.languages,
+            filters.since.as_deref(),
+        )?;
+        if filter.sources.as_ref().is_some_and(|s| s.is_empty()) {
+            return Ok(None);
+        }
 
-            // 🧠 Cache the answer with the current query embedding
-            cache.insert_answer(query.to_string(), context_hash, query_embedding.clone(), raw_answer.clone());
-            cache.save(&config_dir)?;
+        let (hits, missing) = search_index(
+            self.store.as_deref(),
+            state.as_ref(),
+            &self.project,
+            mode,
+            query,
+            query_embedding,
+            options.top_k,
+            &filter,
+        )
+        .await?;
+        self.missing_files = missing;
+        Ok(Some(hits))
+    }
 
-            // Add to session history
-            session_manager.add_interaction(query.to_string(), raw_answer)?;
-            session_manager.save(&config_dir)?;
+    /// Warns about indexed files the last search couldn't read, if any.
+    pub fn missing_files_warning(&self) -> Option<String> {
+        let first = self.missing_files.first()?;
+        let files = match self.missing_files.len() - 1 {
+            0 => format!("{} is indexed but no longer exists", first),
+            1 => format!("{} and 1 other indexed file no longer exist", first),
+            n => format!("{} and {} other indexed files no longer exist", first, n),
+        };
+        Some(format!("⚠️ {}; run `kb index` to update the index", files))
+    }
 
-            println!("💡 Answer:\n\n{}", rendered);
+    /// Asks the LLM to answer `query` from `context_chunks`, following on from
+    /// the active session.
+    pub async fn answer(&mut self, query: &str, context_chunks: &[String]) -> anyhow::Result<String> {
+        self.summarize_history().await?;
+        llm::get_llm_response(self.llm.as_deref().unwrap(), query, context_chunks, Some(&self.sessions), &self.history).await
+    }
 
-            if let Some(session) = session_manager.get_active_session() {
-                println!("\n📝 Session: {} (Q&A: {})",
-                    &session.id[..8],
-                    session.queries.len()
-                );
+    /// Folds the active session's older Q&A pairs into its summary before they
+    /// drop out of the history sent with each question, saving it straight
+    /// away so a failed answer doesn't lose it. Loads the LLM.
+    async fn summarize_history(&mut self) -> anyhow::Result<()> {
+        self.load_llm()?;
+        if let Some(session) = self.sessions.get_active_session_mut() {
+            let changed = llm::summarize_history(self.llm.as_deref().unwrap(), session, &self.history)
+                .await
+                .context("Failed to summarize the earlier conversation")?;
+            if changed {
+                self.sessions.save(&self.config_dir)?;
             }
         }
-        _ => {
-            for r in &results {
-                println!("--- Result {} ---", r.index);
-                println!("📄 Source: {}", r.source);
-                println!("🔎 Distance: {:.4}", r.distance);
-                println!("{}", utils::highlight_syntax(r.content, r.source));
-                println!();
+        Ok(())
+    }
+
+    /// Creates the chat provider on first use.
+    fn load_llm(&mut self) -> anyhow::Result<()> {
+        if self.llm.is_none() {
+            self.llm = Some(llm::from_config(&self.client, &self.app_config)?);
+        }
+        Ok(())
+    }
+
+    /// Where an indexed file is on disk.
+    pub fn file_path(&self, source: &str) -> PathBuf {
+        self.project.file_path(source)
+    }
+
+    /// The embedder and store, created on first use.
+    fn vector_search(&mut self) -> anyhow::Result<(&dyn EmbeddingProvider, &dyn VectorStore)> {
+        if self.embedder.is_none() {
+            self.embedder = Some(embedding::from_config(&self.client, &self.app_config)?);
+        }
+        if self.store.is_none() {
+            self.store = Some(store::from_config(&self.app_config, &self.project.collection(&self.app_config))?);
+        }
+        Ok((self.embedder.as_deref().unwrap(), self.store.as_deref().unwrap()))
+    }
+}
+
+/// Formats a result as a context snippet for the LLM.
+pub fn context_chunk(r: &SearchResult) -> String {
+    let lang = Path::new(r.source)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("text");
+
+    let section = r.breadcrumb
+        .map(|b| format!("\n**Section:** {}", b))
+        .unwrap_or_default();
+
+    format!(
+        "**File:** `{}`{}\n\n```{}\n{}\n```",
+        r.location, section, lang, r.content
+    )
+}
+
+/// Prints results with their source and highlighted content.
+pub fn print_results(results: &[SearchResult]) {
+    for r in results {
+        println!("--- Result {} ---", r.index);
+        match r.breadcrumb {
+            Some(breadcrumb) => println!("📄 Source: {} — {}", r.location, breadcrumb),
+            None => println!("📄 Source: {}", r.location),
+        }
+        if let Some(distance) = r.distance {
+            println!("🔎 Distance: {:.4}", distance);
+        }
+        if let Some(score) = r.score {
+            println!("📊 Score: {:.4}", score);
+        }
+        println!("{}", utils::highlight_syntax(r.content, r.source));
+        println!();
+    }
+}
+
+/// The footer listing the sources an answer cites, flagging citations that
+/// match no context chunk and answers that cite nothing.
+pub fn sources_footer(answer: &str, results: &[SearchResult]) -> String {
+    let citations = check_citations(answer, results.len());
+    let mut footer = String::new();
+
+    if !citations.cited.is_empty() {
+        footer.push_str("\n📚 Sources:\n");
+        for n in &citations.cited {
+            let r = &results[n - 1];
+            match r.breadcrumb {
+                Some(breadcrumb) => footer.push_str(&format!("  [{}] {} — {}\n", n, r.location, breadcrumb)),
+                None => footer.push_str(&format!("  [{}] {}\n", n, r.location)),
             }
         }
     }
 
-    Ok(())
+    if !citations.invalid.is_empty() {
+        let invalid: Vec<String> = citations.invalid.iter().map(|n| format!("[{}]", n)).collect();
+        let (noun, verb) = if invalid.len() == 1 { ("Citation", "doesn't") } else { ("Citations", "don't") };
+        footer.push_str(&format!(
+            "\n⚠️ {} {} {} match any of the {} sources; treat the claims citing them with suspicion\n",
+            noun,
+            invalid.join(", "),
+            verb,
+            results.len()
+        ));
+    } else if citations.cited.is_empty() {
+        footer.push_str("\n⚠️ The answer doesn't cite any of the sources; it may not be grounded in the indexed code\n");
+    }
+
+    footer
 }
 
+/// Runs the vector search, the lexical search, or both fused by rank. Also
+/// returns the indexed files the lexical search couldn't read.
+#[allow(clippy::too_many_arguments)]
+async fn search_index(
+    store: Option<&dyn VectorStore>,
+    state: Option<&IndexState>,
+    project: &Project,
+    mode: SearchMode,
+    query: &str,
+    query_embedding: Option<&[f32]>,
+    top_k: usize,
+    filter: &MetadataFilter,
+) -> anyhow::Result<(Vec<QueryHit>, Vec<String>)> {
+    let candidates = mode.candidates(top_k);
+
+    let semantic = match (store, query_embedding) {
+        (Some(store), Some(embedding)) => store.query(embedding, candidates, filter).await?,
+        _ => Vec::new(),
+    };
+
+    let lexical = match (mode, state) {
+        (SearchMode::Semantic, _) | (_, None) => LexicalResults::default(),
+        (_, Some(state)) => search::lexical_search(state, project, query, candidates, filter)?,
+    };
+
+    let hits = match mode {
+        SearchMode::Semantic => semantic,
+        SearchMode::Lexical => lexical.hits,
+        SearchMode::Hybrid => search::fuse(vec![semantic, lexical.hits], top_k),
+    };
+    Ok((hits, lexical.missing))
+}
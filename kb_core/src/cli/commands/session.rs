@@ -1,14 +1,20 @@
-use crate::{config, state::SessionManager};
+use crate::{project::Project, state::{SessionManager, StateLock}};
 
 
-pub fn handle_sessions(list: bool, clear: bool, switch: Option<String>) -> anyhow::Result<()> {
-    let config_dir = config::get_config_dir()?;
+pub fn handle_sessions(
+    list: bool,
+    clear: bool,
+    switch: Option<String>,
+    project: Option<String>,
+) -> anyhow::Result<()> {
+    let project = Project::resolve(project.as_deref(), &std::env::current_dir()?)?;
+    let config_dir = project.state_dir()?;
+    let _lock = StateLock::sessions(&config_dir)?;
     let mut session_manager = SessionManager::load(&config_dir)?;
 
     if clear {
         if let Some(active_id) = session_manager.active_session.clone() {
-            session_manager.sessions.remove(&active_id);
-            session_manager.active_session = None;
+            session_manager.remove_session(&active_id);
             session_manager.save(&config_dir)?;
             println!("🧹 Cleared session: {}", active_id);
         } else {
@@ -25,30 +31,35 @@ pub fn handle_sessions(list: bool, clear: bool, switch: Option<String>) -> anyho
     }
 
     if list || session_manager.sessions.is_empty() {
-        println!("📋 Available Sessions:");
-        for (id, session) in session_manager.list_sessions() {
-            let active = if Some(id) == session_manager.active_session.as_ref() {
-                "* "
-            } else {
-                "  "
-            };
-
-            let time = chrono::DateTime::<chrono::Utc>::from_timestamp(
-                session.last_updated as i64, 0
-            ).unwrap_or_default().format("%Y-%m-%d %H:%M");
-
-            println!("{}{} - {} Q&A pairs, last updated: {}",
-                active,
-                &id[..8],
-                session.queries.len(),
-                time
-            );
-        }
-
-        if session_manager.sessions.is_empty() {
-            println!("  No sessions found. Create one with 'kb query --session new'");
-        }
+        print_sessions(&session_manager);
     }
 
     Ok(())
 }
+
+/// Lists the sessions, marking the active one.
+pub fn print_sessions(session_manager: &SessionManager) {
+    println!("📋 Available Sessions:");
+    for (id, session) in session_manager.list_sessions() {
+        let active = if Some(id) == session_manager.active_session.as_ref() {
+            "* "
+        } else {
+            "  "
+        };
+
+        let time = chrono::DateTime::<chrono::Utc>::from_timestamp(
+            session.last_updated as i64, 0
+        ).unwrap_or_default().format("%Y-%m-%d %H:%M");
+
+        println!("{}{} - {} Q&A pairs, last updated: {}",
+            active,
+            &id[..8],
+            session.queries.len(),
+            time
+        );
+    }
+
+    if session_manager.sessions.is_empty() {
+        println!("  No sessions found. Create one with 'kb query --session new'");
+    }
+}
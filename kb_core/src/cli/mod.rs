@@ -1,6 +1,6 @@
 pub mod commands;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -11,6 +11,21 @@ pub enum Cli {
     Index {
         /// Path to the file or directory to index
         path: PathBuf,
+        /// Keep running and re-index files as they change
+        #[arg(short, long, default_value_t = false)]
+        watch: bool,
+        /// Project to index into (detected from the path by default)
+        #[arg(long)]
+        project: Option<String>,
+    },
+    /// Re-embed the index with a different embedding model
+    Reindex {
+        /// Embedding model to switch to
+        #[arg(long)]
+        model: String,
+        /// Project to re-embed (detected from the current directory by default)
+        #[arg(long)]
+        project: Option<String>,
     },
     /// Query the index with a text prompt
     Query {
@@ -22,9 +37,50 @@ pub enum Cli {
         /// Output format (pretty, json, markdown, smart)
         #[arg(short, long, default_value = "smart")]
         format: String,
+        /// Search mode (semantic, lexical, hybrid)
+        #[arg(short, long, default_value = "semantic")]
+        mode: String,
         /// Session ID or 'new' to create a new session
         #[arg(long)]
         session: Option<String>,
+        /// Project to query (detected from the current directory by default)
+        #[arg(long)]
+        project: Option<String>,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Ask questions interactively, keeping the conversation in the active session
+    Chat {
+        /// Number of results to retrieve per question
+        #[arg(short, long, default_value_t = 5)]
+        top_k: usize,
+        /// Output format (pretty, json, markdown, smart)
+        #[arg(short, long, default_value = "smart")]
+        format: String,
+        /// Search mode (semantic, lexical, hybrid)
+        #[arg(short, long, default_value = "semantic")]
+        mode: String,
+        /// Project to query (detected from the current directory by default)
+        #[arg(long)]
+        project: Option<String>,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Browse search results in a full-screen terminal UI
+    Browse {
+        /// The query text to search for
+        query: String,
+        /// Number of results to return
+        #[arg(short, long, default_value_t = 20)]
+        top_k: usize,
+        /// Search mode (semantic, lexical, hybrid)
+        #[arg(short, long, default_value = "semantic")]
+        mode: String,
+        /// Project to query (detected from the current directory by default)
+        #[arg(long)]
+        project: Option<String>,
+        #[command(flatten)]
+        filters: FilterArgs,
     },
     /// Manage sessions for conversation history
     Sessions {
@@ -37,7 +93,13 @@ pub enum Cli {
         /// Switch to a specific session by ID
         #[arg(short, long)]
         switch: Option<String>,
+        /// Project whose sessions to manage (detected from the current directory by default)
+        #[arg(long)]
+        project: Option<String>,
     },
+    /// Manage indexed projects
+    #[command(subcommand)]
+    Projects(ProjectsCommand),
     /// Configure the application
     Config {
         /// Set the OpenAI API key
@@ -49,3 +111,42 @@ pub enum Cli {
     },
 }
 
+
+/// Restricts which chunks a query can return.
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// Only search files matching this glob, e.g. 'src/api/**' (repeatable)
+    #[arg(long = "path")]
+    pub paths: Vec<String>,
+    /// Skip files matching this glob (repeatable)
+    #[arg(long)]
+    pub exclude: Vec<String>,
+    /// Only search these languages or extensions, e.g. 'rust,markdown'
+    #[arg(long = "lang", value_delimiter = ',')]
+    pub languages: Vec<String>,
+    /// Only search files modified within this duration (30m, 12h, 7d, 2w) or since a date (2025-01-31)
+    #[arg(long)]
+    pub since: Option<String>,
+}
+
+impl FilterArgs {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.exclude.is_empty() && self.languages.is_empty() && self.since.is_none()
+    }
+
+    /// Whether resolving the filter needs the list of indexed files.
+    pub fn has_paths(&self) -> bool {
+        !self.paths.is_empty() || !self.exclude.is_empty()
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ProjectsCommand {
+    /// List indexed projects
+    List,
+    /// Delete a project's collection and state
+    Remove {
+        /// Name of the project to remove
+        name: String,
+    },
+}
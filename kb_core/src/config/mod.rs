@@ -1,3 +1,4 @@
+use crate::utils;
 use dirs::config_dir;
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -7,11 +8,98 @@ use std::path::PathBuf;
 #[derive(Deserialize, Serialize, Clone)]
 pub struct AppConfig {
     pub chroma_host: String,
+    /// Chroma tenant; defaults to `default_tenant`.
+    pub chroma_tenant: Option<String>,
+    /// Chroma database; defaults to `default_database`.
+    pub chroma_database: Option<String>,
+    /// Collection of the default project; other projects append `_<name>`.
+    pub chroma_collection: Option<String>,
+    /// Token sent with every Chroma request. `CHROMA_AUTH_TOKEN` takes precedence.
+    pub chroma_auth_token: Option<String>,
+    /// Header carrying the token: `authorization` (as a bearer token) or `x-chroma-token`.
+    pub chroma_auth_header: Option<String>,
+    /// Username for Chroma basic auth, used when no token is set.
+    pub chroma_username: Option<String>,
+    pub chroma_password: Option<String>,
+    /// PEM file with an extra CA certificate to trust, e.g. for a self-signed server.
+    pub chroma_ca_cert: Option<PathBuf>,
+    /// Skip TLS certificate verification for Chroma. Only for testing.
+    pub chroma_accept_invalid_certs: Option<bool>,
     pub openai_api_key: Option<String>,
     pub openai_completion_model: String,
     pub openai_embedding_model: String,
     pub file_extensions: Option<Vec<String>>,
     pub syntax_theme: Option<String>,
+    /// One of `openai`, `openai-compatible`, `ollama` or `mock`.
+    pub embedding_provider: Option<String>,
+    /// Base URL for `openai-compatible` and `ollama` embedding providers.
+    pub embedding_base_url: Option<String>,
+    /// API key sent to an `openai-compatible` embedding server.
+    pub embedding_api_key: Option<String>,
+    /// Embedding model name; defaults to `openai_embedding_model`.
+    pub embedding_model: Option<String>,
+    /// One of `openai`, `openai-compatible`, `anthropic` or `ollama`.
+    pub llm_provider: Option<String>,
+    /// Base URL for `openai-compatible`, `anthropic` and `ollama` chat providers.
+    pub llm_base_url: Option<String>,
+    /// API key for `openai-compatible` or `anthropic` chat providers.
+    pub llm_api_key: Option<String>,
+    /// Chat model name; defaults to `openai_completion_model`.
+    pub llm_model: Option<String>,
+    /// Either `chroma` or `local` for the built-in file-backed store.
+    pub vector_store: Option<String>,
+    /// Attempts per HTTP request before giving up on 429s, 5xx and connection errors.
+    pub retry_max_attempts: Option<u32>,
+    /// Delay before the first retry; doubles on each attempt.
+    pub retry_base_delay_ms: Option<u64>,
+    /// Randomize retry delays so concurrent requests don't retry in lockstep.
+    pub retry_jitter: Option<bool>,
+    /// Most recent Q&A pairs sent verbatim with each question; older ones are summarized.
+    pub history_max_turns: Option<usize>,
+    /// Estimated tokens the verbatim Q&A pairs may take up.
+    pub history_token_budget: Option<usize>,
+}
+
+impl AppConfig {
+    pub fn chroma_tenant(&self) -> &str {
+        self.chroma_tenant.as_deref().unwrap_or("default_tenant")
+    }
+
+    pub fn chroma_database(&self) -> &str {
+        self.chroma_database.as_deref().unwrap_or("default_database")
+    }
+
+    pub fn chroma_collection(&self) -> &str {
+        self.chroma_collection.as_deref().unwrap_or("kb_index")
+    }
+
+    pub fn chroma_auth_header(&self) -> &str {
+        self.chroma_auth_header.as_deref().unwrap_or("authorization")
+    }
+
+    pub fn embedding_provider(&self) -> &str {
+        self.embedding_provider.as_deref().unwrap_or("openai")
+    }
+
+    pub fn embedding_model(&self) -> &str {
+        self.embedding_model
+            .as_deref()
+            .unwrap_or(&self.openai_embedding_model)
+    }
+
+    pub fn vector_store(&self) -> &str {
+        self.vector_store.as_deref().unwrap_or("chroma")
+    }
+
+    pub fn llm_provider(&self) -> &str {
+        self.llm_provider.as_deref().unwrap_or("openai")
+    }
+
+    pub fn llm_model(&self) -> &str {
+        self.llm_model
+            .as_deref()
+            .unwrap_or(&self.openai_completion_model)
+    }
 }
 
 
@@ -33,11 +121,34 @@ pub fn load_config() -> anyhow::Result<AppConfig> {
     if !config_path.exists() {
         let default = AppConfig {
             chroma_host: "http://localhost:8000".into(),
+            chroma_tenant: None,
+            chroma_database: None,
+            chroma_collection: None,
+            chroma_auth_token: env::var("CHROMA_AUTH_TOKEN").ok(),
+            chroma_auth_header: None,
+            chroma_username: None,
+            chroma_password: None,
+            chroma_ca_cert: None,
+            chroma_accept_invalid_certs: None,
             openai_api_key: env_api_key,
             openai_completion_model: "gpt-4".to_string(),
             openai_embedding_model: "text-embedding-3-large".to_string(),
             file_extensions: Some(default_extensions()),
             syntax_theme: Some("gruvbox-dark".to_string()),
+            embedding_provider: Some("openai".to_string()),
+            embedding_base_url: None,
+            embedding_api_key: None,
+            embedding_model: None,
+            llm_provider: Some("openai".to_string()),
+            llm_base_url: None,
+            llm_api_key: None,
+            llm_model: None,
+            vector_store: Some("chroma".to_string()),
+            retry_max_attempts: None,
+            retry_base_delay_ms: None,
+            retry_jitter: None,
+            history_max_turns: None,
+            history_token_budget: None,
         };
 
         if let Some(parent) = config_path.parent() {
@@ -57,10 +168,26 @@ pub fn load_config() -> anyhow::Result<AppConfig> {
     if env_api_key.is_some() {
         config.openai_api_key = env_api_key;
     }
+    if let Ok(token) = env::var("CHROMA_AUTH_TOKEN") {
+        config.chroma_auth_token = Some(token);
+    }
 
     Ok(config)
 }
 
+/// Sets one key in `config.toml`, leaving the rest of the file as written.
+/// Returns the file's path.
+pub fn set_config_value(key: &str, value: &str) -> anyhow::Result<PathBuf> {
+    // Make sure the file exists, then edit it as a table so values taken from
+    // environment variables aren't written out
+    load_config()?;
+    let config_path = get_config_dir()?.join("config.toml");
+    let mut table: toml::Table = toml::from_str(&fs::read_to_string(&config_path)?)?;
+    table.insert(key.to_string(), toml::Value::String(value.to_string()));
+    utils::write_atomic(&config_path, toml::to_string_pretty(&table)?.as_bytes())?;
+    Ok(config_path)
+}
+
 pub fn get_openai_api_key() -> anyhow::Result<String> {
     match env::var("OPENAI_API_KEY") {
         Ok(key) => {
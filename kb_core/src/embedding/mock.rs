@@ -0,0 +1,50 @@
+use super::EmbeddingProvider;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+pub const MOCK_DIMENSION: usize = 256;
+
+/// Deterministic, offline embeddings built by hashing tokens into a fixed
+/// number of buckets. Texts sharing words end up close together, which is
+/// enough to exercise indexing and search without a model.
+pub struct MockEmbedder {
+    dimension: usize,
+}
+
+impl MockEmbedder {
+    pub fn new(dimension: usize) -> Self {
+        MockEmbedder { dimension }
+    }
+
+    fn embed_text(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimension];
+
+        for token in text
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|t| !t.is_empty())
+        {
+            let digest = Sha256::digest(token.to_lowercase().as_bytes());
+            let bucket = u64::from_le_bytes(digest[..8].try_into().unwrap()) as usize % self.dimension;
+            let sign = if digest[8] & 1 == 0 { 1.0 } else { -1.0 };
+            vector[bucket] += sign;
+        }
+
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+
+        vector
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for MockEmbedder {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_text(text)).collect())
+    }
+
+    fn model(&self) -> &str {
+        "mock"
+    }
+}
@@ -1,95 +1,157 @@
-use crate::config;
-use reqwest::Client;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+
+use crate::config::{self, AppConfig};
+use crate::retry::RetryPolicy;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-
-#[derive(Serialize)]
-pub struct EmbeddingRequest {
-    input: Vec<String>,
-    model: String,
+use std::fmt;
+
+pub use mock::MockEmbedder;
+pub use ollama::OllamaEmbedder;
+pub use openai::OpenAiEmbedder;
+
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    /// Embeds a batch of texts, returning one vector per input in the same order.
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>>;
+
+    /// Name of the model producing the vectors.
+    fn model(&self) -> &str;
+
+    async fn embed_one(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+        self.embed(&[text.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Embedding provider returned no vectors"))
+    }
 }
 
-#[derive(Deserialize)]
-struct EmbeddingResponse {
-    data: Vec<EmbeddingData>,
+/// An embedding request the provider turned down as invalid, such as an input
+/// over the model's token limit. Sending the same texts again won't help, but
+/// a smaller batch without the offending text may succeed.
+#[derive(Debug)]
+pub struct RejectedRequest {
+    pub status: StatusCode,
+    pub body: String,
 }
 
-#[derive(Deserialize)]
-struct EmbeddingData {
-    embedding: Vec<f32>,
+impl RejectedRequest {
+    /// Whether a failed response means the request itself was rejected;
+    /// rate limits are client errors too but pass with time.
+    pub fn matches(status: StatusCode) -> bool {
+        status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS
+    }
+
+    /// Whether `error` is a rejected request.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<RejectedRequest>().is_some()
+    }
 }
 
-pub async fn get_embeddings(client: &Client, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
-    let config = config::load_config()?;
-    let api_key = config::get_openai_api_key()?;
+impl fmt::Display for RejectedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Embedding request rejected: HTTP {} - {}", self.status, self.body)
+    }
+}
 
-    let body = serde_json::json!({
-        "model": config.openai_embedding_model,
-        "input": texts
-    });
+impl std::error::Error for RejectedRequest {}
 
-    let res = client
-        .post("https://api.openai.com/v1/embeddings")
-        .bearer_auth(api_key)
-        .json(&body)
-        .send()
-        .await?;
+/// The model behind a collection's vectors. Vectors from different models
+/// can't be compared, so a collection records the one it was built with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbeddingInfo {
+    pub model: String,
+    pub dimension: usize,
+}
 
-    let status = res.status();
-    let text_body = res.text().await?;
+impl EmbeddingInfo {
+    /// Embeds a short text to learn the provider's vector dimension.
+    pub async fn probe(embedder: &dyn EmbeddingProvider) -> anyhow::Result<Self> {
+        let embedding = embedder.embed_one("kb-index").await?;
+        Ok(Self::of(embedder, &embedding))
+    }
 
-    if !status.is_success() {
-        eprintln!("❌ OpenAI error: HTTP {} - {}", status, text_body);
-        anyhow::bail!("Embedding batch failed");
+    /// Describes an embedding the provider has already produced.
+    pub fn of(embedder: &dyn EmbeddingProvider, embedding: &[f32]) -> Self {
+        EmbeddingInfo {
+            model: embedder.model().to_string(),
+            dimension: embedding.len(),
+        }
     }
 
-    let parsed: serde_json::Value = serde_json::from_str(&text_body)?;
-    let data = parsed["data"]
-        .as_array()
-        .ok_or_else(|| anyhow::anyhow!("Invalid embedding response format"))?;
-
-    Ok(data.iter()
-        .map(|v| {
-            v["embedding"]
-                .as_array()
-                .unwrap_or(&vec![])
-                .iter()
-                .map(|f| f.as_f64().unwrap_or_default() as f32)
-                .collect()
+    /// Reads the model recorded in a collection's metadata, if any.
+    pub fn from_metadata(metadata: &serde_json::Value) -> Option<Self> {
+        Some(EmbeddingInfo {
+            model: metadata.get("embedding_model")?.as_str()?.to_string(),
+            dimension: metadata.get("embedding_dimension")?.as_u64()? as usize,
         })
-        .collect())
-}
-
-pub async fn get_embedding(client: &Client, text: &str) -> anyhow::Result<Vec<f32>> {
-    let config = config::load_config()?;
-    let body = EmbeddingRequest {
-        input: vec![text.to_string()],
-        model: config.openai_embedding_model.into(),
-    };
-
-    // Get API key from config or environment
-    let api_key = config::get_openai_api_key()?;
+    }
 
-    let response = client
-        .post("https://api.openai.com/v1/embeddings")
-        .bearer_auth(api_key)
-        .json(&body)
-        .send()
-        .await?;
+    pub fn to_metadata(&self) -> serde_json::Value {
+        serde_json::json!({
+            "embedding_model": self.model,
+            "embedding_dimension": self.dimension,
+        })
+    }
 
-    let status = response.status();
-    let text_body = response.text().await?;
+    /// Fails unless `configured` produces vectors comparable to this recorded model's.
+    pub fn check(&self, configured: &EmbeddingInfo) -> anyhow::Result<()> {
+        if self == configured {
+            return Ok(());
+        }
 
-    if !status.is_success() {
-        println!("❌ OpenAI error: HTTP {} - {}", status, text_body);
-        anyhow::bail!("OpenAI returned an error");
+        anyhow::bail!(
+            "The index was built with embedding model '{}' ({} dimensions), but the configured model is '{}' ({} dimensions). \
+             Vectors from different models can't be compared. Run `kb reindex --model {}` to re-embed the index, \
+             or set embedding_model back to '{}'",
+            self.model,
+            self.dimension,
+            configured.model,
+            configured.dimension,
+            configured.model,
+            self.model
+        )
     }
+}
 
-    match serde_json::from_str::<EmbeddingResponse>(&text_body) {
-        Ok(parsed) => Ok(parsed.data.into_iter().next().unwrap().embedding),
-        Err(err) => {
-            println!("❌ Failed to parse response JSON: {}", err);
-            println!("Raw response:\n{}", text_body);
-            Err(err.into())
+/// Builds the embedding provider selected by `embedding_provider` in the config.
+pub fn from_config(client: &Client, config: &AppConfig) -> anyhow::Result<Box<dyn EmbeddingProvider>> {
+    let model = config.embedding_model();
+    let retry = RetryPolicy::from_config(config);
+
+    let provider: Box<dyn EmbeddingProvider> = match config.embedding_provider() {
+        "openai" => Box::new(OpenAiEmbedder::new(
+            client.clone(),
+            openai::OPENAI_BASE_URL,
+            Some(config::get_openai_api_key()?),
+            model,
+        ).with_retry(retry)),
+        "openai-compatible" => {
+            let base_url = config.embedding_base_url.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("embedding_base_url must be set to use the openai-compatible embedding provider")
+            })?;
+            Box::new(OpenAiEmbedder::new(
+                client.clone(),
+                base_url,
+                config.embedding_api_key.clone(),
+                model,
+            ).with_retry(retry))
         }
-    }
+        "ollama" => Box::new(OllamaEmbedder::new(
+            client.clone(),
+            config.embedding_base_url.as_deref().unwrap_or(ollama::OLLAMA_BASE_URL),
+            model,
+        ).with_retry(retry)),
+        "mock" => Box::new(MockEmbedder::new(mock::MOCK_DIMENSION)),
+        other => anyhow::bail!(
+            "Unknown embedding provider '{}'. Expected one of: openai, openai-compatible, ollama, mock",
+            other
+        ),
+    };
+
+    Ok(provider)
 }
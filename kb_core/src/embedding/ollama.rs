@@ -0,0 +1,80 @@
+use super::{EmbeddingProvider, RejectedRequest};
+use crate::retry::{self, RetryPolicy};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+pub const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Serialize)]
+struct OllamaEmbeddingRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbeddingResponse {
+    embedding: Vec<f32>,
+}
+
+/// Embeddings from a local Ollama server via `/api/embeddings`.
+pub struct OllamaEmbedder {
+    client: Client,
+    base_url: String,
+    model: String,
+    retry: RetryPolicy,
+}
+
+impl OllamaEmbedder {
+    pub fn new(client: Client, base_url: &str, model: &str) -> Self {
+        OllamaEmbedder {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OllamaEmbedder {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let url = format!("{}/api/embeddings", self.base_url);
+        let mut embeddings = Vec::with_capacity(texts.len());
+
+        // The endpoint takes a single prompt per request
+        for text in texts {
+            let body = OllamaEmbeddingRequest {
+                model: &self.model,
+                prompt: text,
+            };
+
+            let response = retry::send_with_retry(self.client.post(&url).json(&body), &self.retry).await?;
+            let status = response.status();
+            let text_body = response.text().await?;
+
+            if RejectedRequest::matches(status) {
+                return Err(RejectedRequest { status, body: text_body }.into());
+            }
+            if !status.is_success() {
+                eprintln!("❌ Ollama error: HTTP {} - {}", status, text_body);
+                anyhow::bail!("Ollama embedding request failed");
+            }
+
+            let parsed: OllamaEmbeddingResponse = serde_json::from_str(&text_body)
+                .map_err(|err| anyhow::anyhow!("Failed to parse Ollama response: {}", err))?;
+            embeddings.push(parsed.embedding);
+        }
+
+        Ok(embeddings)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
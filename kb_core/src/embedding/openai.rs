@@ -0,0 +1,96 @@
+use super::{EmbeddingProvider, RejectedRequest};
+use crate::retry::{self, RetryPolicy};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    input: &'a [String],
+    model: &'a str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+/// Embeddings from OpenAI or any server exposing the OpenAI `/embeddings` API.
+pub struct OpenAiEmbedder {
+    client: Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    retry: RetryPolicy,
+}
+
+impl OpenAiEmbedder {
+    pub fn new(client: Client, base_url: &str, api_key: Option<String>, model: &str) -> Self {
+        OpenAiEmbedder {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAiEmbedder {
+    async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let body = EmbeddingRequest {
+            input: texts,
+            model: &self.model,
+        };
+
+        let mut request = self
+            .client
+            .post(format!("{}/embeddings", self.base_url))
+            .json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = retry::send_with_retry(request, &self.retry).await?;
+        let status = response.status();
+        let text_body = response.text().await?;
+
+        if RejectedRequest::matches(status) {
+            return Err(RejectedRequest { status, body: text_body }.into());
+        }
+        if !status.is_success() {
+            eprintln!("❌ Embedding error: HTTP {} - {}", status, text_body);
+            anyhow::bail!("Embedding request failed");
+        }
+
+        let parsed: EmbeddingResponse = serde_json::from_str(&text_body)
+            .map_err(|err| anyhow::anyhow!("Failed to parse embedding response: {}", err))?;
+
+        if parsed.data.len() != texts.len() {
+            anyhow::bail!(
+                "Embedding response contained {} vectors for {} inputs",
+                parsed.data.len(),
+                texts.len()
+            );
+        }
+
+        Ok(parsed.data.into_iter().map(|d| d.embedding).collect())
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
@@ -1,7 +1,12 @@
 pub mod chroma;
+pub mod chunking;
 pub mod cli;
 pub mod config;
 pub mod embedding;
 pub mod utils;
 pub mod llm;
+pub mod project;
+pub mod retry;
+pub mod search;
 pub mod state;
+pub mod store;
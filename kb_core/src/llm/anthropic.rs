@@ -0,0 +1,126 @@
+use super::{ChatMessage, LlmProvider, TEMPERATURE};
+use crate::retry::{self, RetryPolicy};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+
+pub const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const MAX_TOKENS: u32 = 4096;
+
+/// Answers from the Anthropic Messages API.
+pub struct AnthropicLlm {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+    retry: RetryPolicy,
+}
+
+impl AnthropicLlm {
+    pub fn new(client: Client, base_url: &str, api_key: String, model: &str) -> Self {
+        AnthropicLlm {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        // System prompts are a top-level field rather than a message role
+        let system = messages
+            .iter()
+            .filter(|m| m.role == "system")
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let conversation: Vec<&ChatMessage> = messages.iter().filter(|m| m.role != "system").collect();
+
+        let body = serde_json::json!({
+            "model": self.model,
+            "max_tokens": MAX_TOKENS,
+            "system": system,
+            "messages": conversation,
+            "temperature": TEMPERATURE,
+            "stream": stream
+        });
+
+        self.client
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicLlm {
+    async fn complete(&self, messages: &[ChatMessage]) -> anyhow::Result<String> {
+        let res = retry::send_with_retry(self.request(messages, false), &self.retry).await?;
+        let status = res.status();
+        let text = res.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Anthropic request failed: HTTP {} - {}", status, text);
+        }
+
+        let value: serde_json::Value = serde_json::from_str(&text)?;
+        let answer = value["content"]
+            .as_array()
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .filter(|b| b["type"] == "text")
+                    .filter_map(|b| b["text"].as_str())
+                    .collect::<String>()
+            })
+            .filter(|answer| !answer.is_empty())
+            .unwrap_or_else(|| "No answer generated".to_string());
+
+        Ok(answer)
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> anyhow::Result<String> {
+        let res = retry::send_with_retry(self.request(messages, true), &self.retry).await?;
+        let status = res.status();
+        if !status.is_success() {
+            anyhow::bail!("Anthropic request failed: HTTP {} - {}", status, res.text().await?);
+        }
+
+        let mut answer = String::new();
+        super::for_each_line(res, |line| {
+            let Some(data) = super::sse_data(line) else {
+                return Ok(());
+            };
+            let event: serde_json::Value = serde_json::from_str(data)?;
+            match event["type"].as_str() {
+                Some("content_block_delta") if event["delta"]["type"] == "text_delta" => {
+                    if let Some(token) = event["delta"]["text"].as_str() {
+                        answer.push_str(token);
+                        on_token(token);
+                    }
+                }
+                Some("error") => anyhow::bail!("Anthropic stream failed: {}", event["error"]),
+                _ => {}
+            }
+            Ok(())
+        })
+        .await?;
+
+        Ok(answer)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
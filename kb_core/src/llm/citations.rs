@@ -0,0 +1,59 @@
+use regex::Regex;
+use std::collections::BTreeSet;
+
+/// `[n]` citations found in an answer, checked against the context it was given.
+#[derive(Debug, Default)]
+pub struct Citations {
+    /// Cited context chunks, 1-based and in order.
+    pub cited: Vec<usize>,
+    /// Cited numbers with no matching context chunk.
+    pub invalid: Vec<usize>,
+}
+
+/// Finds the `[n]` and `[n, m]` citations in an answer with `source_count`
+/// numbered context chunks. Code is skipped so indexing like `items[1]`
+/// isn't mistaken for a citation, as are Markdown links like `[1](url)`.
+pub fn check_citations(answer: &str, source_count: usize) -> Citations {
+    let code = Regex::new(r"(?s)```.*?(```|$)|`[^`\n]*`").unwrap();
+    let citation = Regex::new(r"\[(\d+(?:\s*,\s*\d+)*)\]").unwrap();
+    let prose = code.replace_all(answer, " ");
+
+    let mut numbers = BTreeSet::new();
+    for cap in citation.captures_iter(&prose) {
+        let whole = cap.get(0).unwrap();
+        if prose[whole.end()..].starts_with('(') {
+            continue;
+        }
+        numbers.extend(cap[1].split(',').filter_map(|n| n.trim().parse::<usize>().ok()));
+    }
+
+    let (cited, invalid) = numbers.into_iter().partition(|&n| n >= 1 && n <= source_count);
+    Citations { cited, invalid }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_single_and_grouped_citations() {
+        let citations = check_citations("Parsing happens in [2], then [1, 3] and [3][1].", 3);
+        assert_eq!(citations.cited, [1, 2, 3]);
+        assert!(citations.invalid.is_empty());
+    }
+
+    #[test]
+    fn reports_numbers_without_a_source() {
+        let citations = check_citations("See [0], [2] and [7].", 2);
+        assert_eq!(citations.cited, [2]);
+        assert_eq!(citations.invalid, [0, 7]);
+    }
+
+    #[test]
+    fn skips_code_and_links() {
+        let answer = "Use `items[4]`:\n\n```rust\nlet x = v[5];\n```\n\nSee [6](https://example.com) and [1].";
+        let citations = check_citations(answer, 2);
+        assert_eq!(citations.cited, [1]);
+        assert!(citations.invalid.is_empty());
+    }
+}
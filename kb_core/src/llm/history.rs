@@ -0,0 +1,160 @@
+use super::{ChatMessage, LlmProvider};
+use crate::config::AppConfig;
+use crate::state::SessionState;
+use crate::utils;
+
+const DEFAULT_MAX_TURNS: usize = 5;
+const DEFAULT_TOKEN_BUDGET: usize = 2000;
+/// Estimated tokens of Q&A pairs folded into the summary per request, so a
+/// long backlog of turns is summarized over several requests.
+const SUMMARY_BATCH_TOKENS: usize = 8000;
+
+const SUMMARY_PROMPT: &str = "You maintain a running summary of a conversation between a developer and a code assistant. \
+Update the summary with the new exchanges. Keep the questions asked, the conclusions reached, and the files, \
+identifiers and decisions mentioned, so later questions can refer back to them. Be concise: a few short paragraphs \
+or bullet points, at most about 300 words. Reply with the updated summary only.";
+
+/// How much of a session's history is sent verbatim with each question.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryPolicy {
+    pub max_turns: usize,
+    pub token_budget: usize,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        HistoryPolicy {
+            max_turns: DEFAULT_MAX_TURNS,
+            token_budget: DEFAULT_TOKEN_BUDGET,
+        }
+    }
+}
+
+impl HistoryPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        HistoryPolicy {
+            max_turns: config.history_max_turns.unwrap_or(DEFAULT_MAX_TURNS),
+            token_budget: config.history_token_budget.unwrap_or(DEFAULT_TOKEN_BUDGET),
+        }
+    }
+
+    /// Index of the first Q&A pair sent verbatim: the most recent pairs that
+    /// fit within `max_turns` and `token_budget`, stopping at the first that
+    /// doesn't.
+    pub fn verbatim_start(&self, session: &SessionState) -> usize {
+        let mut start = session.queries.len().min(session.responses.len());
+        let mut tokens = 0;
+
+        while start > 0 && session.queries.len() - start < self.max_turns {
+            let turn = turn_tokens(session, start - 1);
+            if tokens + turn > self.token_budget {
+                break;
+            }
+            tokens += turn;
+            start -= 1;
+        }
+
+        start
+    }
+}
+
+/// Folds the Q&A pairs that no longer fit verbatim into the session's rolling
+/// summary. Returns whether the summary changed.
+pub async fn summarize_history(
+    llm: &dyn LlmProvider,
+    session: &mut SessionState,
+    policy: &HistoryPolicy,
+) -> anyhow::Result<bool> {
+    let start = policy.verbatim_start(session);
+    let changed = session.summarized < start;
+
+    while session.summarized < start {
+        // Always take at least one pair so an oversized one can't stall the summary
+        let mut end = session.summarized + 1;
+        let mut tokens = turn_tokens(session, session.summarized);
+        while end < start && tokens + turn_tokens(session, end) <= SUMMARY_BATCH_TOKENS {
+            tokens += turn_tokens(session, end);
+            end += 1;
+        }
+
+        let exchanges = (session.summarized..end)
+            .map(|i| format!("User: {}\n\nAssistant: {}", session.queries[i], session.responses[i]))
+            .collect::<Vec<_>>()
+            .join("\n\n---\n\n");
+        let messages = [
+            ChatMessage::system(SUMMARY_PROMPT),
+            ChatMessage::user(format!(
+                "Summary so far:\n{}\n\nNew exchanges:\n{}",
+                session.summary.as_deref().unwrap_or("(none yet)"),
+                exchanges
+            )),
+        ];
+
+        let summary = llm.complete(&messages).await?;
+        session.summary = Some(summary.trim().to_string());
+        session.summarized = end;
+    }
+
+    Ok(changed)
+}
+
+fn turn_tokens(session: &SessionState, i: usize) -> usize {
+    utils::estimate_tokens(&session.queries[i]) + utils::estimate_tokens(&session.responses[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::ScriptedLlm;
+
+    fn session(turns: usize) -> SessionState {
+        SessionState {
+            queries: (0..turns).map(|i| format!("question {}", i)).collect(),
+            responses: (0..turns).map(|i| format!("answer {}", i)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn summarizes_turns_that_no_longer_fit() {
+        let llm = ScriptedLlm::new(["  First two turns  "]);
+        let mut session = session(4);
+        let policy = HistoryPolicy { max_turns: 2, token_budget: 1000 };
+
+        assert!(summarize_history(&llm, &mut session, &policy).await.unwrap());
+        assert_eq!(session.summary.as_deref(), Some("First two turns"));
+        assert_eq!(session.summarized, 2);
+
+        let requests = llm.requests();
+        assert_eq!(requests.len(), 1);
+        let prompt = &requests[0][1].content;
+        assert!(prompt.contains("Summary so far:\n(none yet)"));
+        assert!(prompt.contains("User: question 1\n\nAssistant: answer 1"));
+        assert!(!prompt.contains("question 2"));
+    }
+
+    #[tokio::test]
+    async fn extends_the_existing_summary() {
+        let llm = ScriptedLlm::new(["Three turns"]);
+        let mut session = session(4);
+        session.summary = Some("Two turns".to_string());
+        session.summarized = 2;
+        let policy = HistoryPolicy { max_turns: 1, token_budget: 1000 };
+
+        assert!(summarize_history(&llm, &mut session, &policy).await.unwrap());
+        assert_eq!(session.summarized, 3);
+        let prompt = &llm.requests()[0][1].content;
+        assert!(prompt.contains("Summary so far:\nTwo turns"));
+        assert!(prompt.contains("question 2") && !prompt.contains("question 1"));
+    }
+
+    #[tokio::test]
+    async fn leaves_history_that_fits_alone() {
+        let llm = ScriptedLlm::default();
+        let mut session = session(2);
+
+        assert!(!summarize_history(&llm, &mut session, &HistoryPolicy::default()).await.unwrap());
+        assert!(session.summary.is_none());
+        assert!(llm.requests().is_empty());
+    }
+}
@@ -1,118 +1,293 @@
-use crate::config;
-use crate::embedding;
+pub mod anthropic;
+pub mod citations;
+pub mod history;
+pub mod ollama;
+pub mod openai;
+pub mod scripted;
+
+use crate::config::{self, AppConfig};
+use crate::retry::RetryPolicy;
 use crate::state::SessionManager;
-use crate::state::{QueryState, hash_query_context};
-use reqwest::Client;
+use async_trait::async_trait;
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+
+pub use anthropic::AnthropicLlm;
+pub use history::{summarize_history, HistoryPolicy};
+pub use ollama::OllamaLlm;
+pub use openai::OpenAiLlm;
+pub use scripted::ScriptedLlm;
+
+const TEMPERATURE: f32 = 0.4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        ChatMessage { role: "system".to_string(), content: content.into() }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        ChatMessage { role: "user".to_string(), content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        ChatMessage { role: "assistant".to_string(), content: content.into() }
+    }
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Sends the conversation and returns the assistant's reply.
+    async fn complete(&self, messages: &[ChatMessage]) -> anyhow::Result<String>;
+
+    /// Like [`complete`](Self::complete), but passes the reply to `on_token`
+    /// piece by piece as it's generated. Providers that can't stream deliver
+    /// it in one piece.
+    async fn complete_stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> anyhow::Result<String> {
+        let answer = self.complete(messages).await?;
+        on_token(&answer);
+        Ok(answer)
+    }
+
+    /// Name of the model answering.
+    fn model(&self) -> &str;
+}
+
+/// Builds the chat provider selected by `llm_provider` in the config.
+pub fn from_config(client: &Client, config: &AppConfig) -> anyhow::Result<Box<dyn LlmProvider>> {
+    let model = config.llm_model();
+    let retry = RetryPolicy::from_config(config);
+
+    let provider: Box<dyn LlmProvider> = match config.llm_provider() {
+        "openai" => Box::new(OpenAiLlm::new(
+            client.clone(),
+            openai::OPENAI_BASE_URL,
+            Some(config::get_openai_api_key()?),
+            model,
+        ).with_retry(retry)),
+        "openai-compatible" => {
+            let base_url = config.llm_base_url.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("llm_base_url must be set to use the openai-compatible LLM provider")
+            })?;
+            Box::new(OpenAiLlm::new(client.clone(), base_url, config.llm_api_key.clone(), model).with_retry(retry))
+        }
+        "anthropic" => {
+            let api_key = config
+                .llm_api_key
+                .clone()
+                .or_else(|| std::env::var("ANTHROPIC_API_KEY").ok().filter(|k| !k.is_empty()))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Anthropic API key not found. Set ANTHROPIC_API_KEY or llm_api_key in the config file.")
+                })?;
+            Box::new(AnthropicLlm::new(
+                client.clone(),
+                config.llm_base_url.as_deref().unwrap_or(anthropic::ANTHROPIC_BASE_URL),
+                api_key,
+                model,
+            ).with_retry(retry))
+        }
+        "ollama" => Box::new(OllamaLlm::new(
+            client.clone(),
+            config.llm_base_url.as_deref().unwrap_or(ollama::OLLAMA_BASE_URL),
+            model,
+        ).with_retry(retry)),
+        other => anyhow::bail!(
+            "Unknown LLM provider '{}'. Expected one of: openai, openai-compatible, anthropic, ollama",
+            other
+        ),
+    };
+
+    Ok(provider)
+}
 
 pub async fn get_llm_response(
-    client: &Client,
+    llm: &dyn LlmProvider,
     prompt: &str,
     context_chunks: &[String],
     session_manager: Option<&SessionManager>,
+    history: &HistoryPolicy,
 ) -> anyhow::Result<String> {
-    let api_key = config::get_openai_api_key()?;
-    let cfg = config::load_config()?;
-    let config_dir = config::get_config_dir()?;
-    let mut state = QueryState::load(&config_dir)?;
-
-    let context_hash = hash_query_context(prompt, context_chunks);
+    let messages = build_messages(prompt, context_chunks, session_manager, history);
+    llm.complete(&messages).await
+}
 
-    // Generate query embedding (for similarity + caching)
-    let embedding = embedding::get_embedding(client, prompt).await?;
+/// Streaming form of [`get_llm_response`]; `on_token` sees the answer as it arrives.
+pub async fn stream_llm_response(
+    llm: &dyn LlmProvider,
+    prompt: &str,
+    context_chunks: &[String],
+    session_manager: Option<&SessionManager>,
+    history: &HistoryPolicy,
+    on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+) -> anyhow::Result<String> {
+    let messages = build_messages(prompt, context_chunks, session_manager, history);
+    llm.complete_stream(&messages, on_token).await
+}
 
-    if let Some(similar) = state.find_similar(&embedding, 0.93) {
-        return Ok(similar);
+/// Calls `on_line` with each line of a streamed response body as it arrives.
+async fn for_each_line(
+    mut res: Response,
+    mut on_line: impl FnMut(&str) -> anyhow::Result<()> + Send,
+) -> anyhow::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        buf.extend_from_slice(&chunk);
+        // Split on bytes so multi-byte characters spanning chunks stay intact
+        while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=end).collect();
+            on_line(String::from_utf8_lossy(&line).trim_end())?;
+        }
     }
-
-    // Check for cached similar answer
-    if let Some(cached) = state.get_cached_answer(prompt, &context_hash) {
-        return Ok(cached);
+    if !buf.is_empty() {
+        on_line(String::from_utf8_lossy(&buf).trim_end())?;
     }
+    Ok(())
+}
+
+/// The payload of a server-sent event's `data:` line.
+fn sse_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(str::trim_start)
+}
 
-    // Prepare full prompt with session context if available
-    let full_context = context_chunks.join("\n\n---\n\n");
+fn build_messages(
+    prompt: &str,
+    context_chunks: &[String],
+    session_manager: Option<&SessionManager>,
+    history: &HistoryPolicy,
+) -> Vec<ChatMessage> {
+    // Number the snippets so the answer can cite them
+    let full_context = context_chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| format!("[{}] {}", i + 1, chunk))
+        .collect::<Vec<_>>()
+        .join("\n\n---\n\n");
 
     let mut messages = vec![
-        serde_json::json!({
-            "role": "system",
-            "content": "You are an expert personal and code assistant. Always format code blocks properly with triple backticks and language specifiers, like ```rust or ```typescript. Never use shorthand language identifiers without backticks."
-        }),
+        ChatMessage::system(
+            "You are an expert personal and code assistant. Always format code blocks properly with triple backticks and language specifiers, like ```rust or ```typescript. Never use shorthand language identifiers without backticks."
+        ),
     ];
 
-    if let Some(manager) = session_manager {
-        if let Some(session) = manager.get_active_session() {
-            // Only include the last 5 interactions (or fewer if the session is shorter)
-            let window_size = 5;
-            let start_idx = session.queries.len().saturating_sub(window_size);
-
-            for (q, r) in session.queries[start_idx..].iter().zip(session.responses[start_idx..].iter()) {
-                messages.push(serde_json::json!({
-                    "role": "user",
-                    "content": q
-                }));
-
-                messages.push(serde_json::json!({
-                    "role": "assistant",
-                    "content": r
-                }));
-            }
-
-            // If we're windowing, add a note about it
-            if start_idx > 0 {
-                let context_note = format!(
-                    "Note: This conversation has {} previous messages that aren't shown here. I'm continuing from where we left off.",
-                    start_idx
-                );
-
-                // Insert this at the beginning of the messages
-                messages.insert(1, serde_json::json!({
-                    "role": "system",
-                    "content": context_note
-                }));
-            }
+    if let Some(session) = session_manager.and_then(|m| m.get_active_session()) {
+        // Recent Q&A pairs go in verbatim, older ones through the rolling summary
+        let start = history.verbatim_start(session).max(session.summarized);
+
+        if let Some(summary) = &session.summary {
+            messages.push(ChatMessage::system(format!(
+                "Summary of the earlier conversation:\n{}",
+                summary
+            )));
+        }
+
+        // Pairs that are neither summarized nor recent enough to include
+        let omitted = start - session.summarized;
+        if omitted > 0 {
+            messages.push(ChatMessage::system(format!(
+                "Note: This conversation has {} previous messages that aren't shown here. I'm continuing from where we left off.",
+                omitted
+            )));
         }
-    }
 
+        for (q, r) in session.queries[start..].iter().zip(session.responses[start..].iter()) {
+            messages.push(ChatMessage::user(q.as_str()));
+            messages.push(ChatMessage::assistant(r.as_str()));
+        }
+    }
 
     // Add current query with context
     let user_content = format!(
         "Use the following code snippets to answer the question. \
          Format your response in Markdown and include code where necessary.\n\n\
+         Cite the snippets that support each claim by their numbers in square brackets, like [1] or [2][3]. \
+         Only cite numbers of snippets listed below, and don't cite anything the snippets don't support.\n\n\
          IMPORTANT: Always use proper markdown code blocks with triple backticks and language specifiers, \
          like ```typescript or ```rust. DO NOT use shorthand like 'ts' or 'tsx' without the triple backticks.\n\n\
          Question:\n{}\n\nContext:\n{}",
         prompt, full_context
     );
 
-    messages.push(serde_json::json!({
-        "role": "user",
-        "content": user_content
-    }));
+    messages.push(ChatMessage::user(user_content));
+    messages
+}
 
-    let body = serde_json::json!({
-        "model": cfg.openai_completion_model,
-        "messages": messages,
-        "temperature": 0.4
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let res = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&body)
-        .send()
-        .await?;
+    fn session_with_turns(turns: usize) -> SessionManager {
+        let mut sessions = SessionManager::default();
+        sessions.create_session();
+        for i in 0..turns {
+            sessions.add_interaction(format!("question {}", i), format!("answer {}", i)).unwrap();
+        }
+        sessions
+    }
 
-    let text = res.text().await?;
-    let value: serde_json::Value = serde_json::from_str(&text)?;
+    #[test]
+    fn build_messages_numbers_context_chunks() {
+        let chunks = vec!["fn a() {}".to_string(), "fn b() {}".to_string()];
+        let messages = build_messages("What does a do?", &chunks, None, &HistoryPolicy::default());
 
-    let answer = value["choices"][0]["message"]["content"]
-        .as_str()
-        .unwrap_or("No answer generated")
-        .to_string();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
+        let question = &messages[1];
+        assert_eq!(question.role, "user");
+        assert!(question.content.contains("Question:\nWhat does a do?"));
+        assert!(question.content.contains("[1] fn a() {}\n\n---\n\n[2] fn b() {}"));
+    }
 
-    state.insert_answer(prompt.to_string(), context_hash, embedding, answer.clone());
-    state.save(&config_dir)?;
+    #[test]
+    fn build_messages_sends_recent_turns_and_notes_omitted_ones() {
+        let sessions = session_with_turns(4);
+        let history = HistoryPolicy { max_turns: 2, token_budget: 1000 };
+        let messages = build_messages("next", &[], Some(&sessions), &history);
 
-    Ok(answer)
-}
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "system", "user", "assistant", "user", "assistant", "user"]);
+        assert!(messages[1].content.contains("2 previous messages"));
+        assert_eq!(messages[2].content, "question 2");
+        assert_eq!(messages[5].content, "answer 3");
+    }
+
+    #[test]
+    fn build_messages_includes_the_summary() {
+        let mut sessions = session_with_turns(3);
+        let session = sessions.get_active_session_mut().unwrap();
+        session.summary = Some("Talked about a and b".to_string());
+        session.summarized = 2;
+        let history = HistoryPolicy { max_turns: 1, token_budget: 1000 };
+        let messages = build_messages("next", &[], Some(&sessions), &history);
+
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "system", "user", "assistant", "user"]);
+        assert!(messages[1].content.ends_with("Talked about a and b"));
+        assert_eq!(messages[2].content, "question 2");
+    }
 
+    #[tokio::test]
+    async fn get_llm_response_sends_the_built_messages() {
+        let llm = ScriptedLlm::new(["It returns nothing [1]"]);
+        let chunks = vec!["fn a() {}".to_string()];
+        let answer = get_llm_response(&llm, "What does a do?", &chunks, None, &HistoryPolicy::default())
+            .await
+            .unwrap();
+
+        assert_eq!(answer, "It returns nothing [1]");
+        let requests = llm.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].last().unwrap().content,
+            build_messages("What does a do?", &chunks, None, &HistoryPolicy::default())[1].content
+        );
+    }
+}
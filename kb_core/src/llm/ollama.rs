@@ -0,0 +1,98 @@
+use super::{ChatMessage, LlmProvider, TEMPERATURE};
+use crate::retry::{self, RetryPolicy};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+
+pub const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+/// Answers from a local Ollama server via `/api/chat`.
+pub struct OllamaLlm {
+    client: Client,
+    base_url: String,
+    model: String,
+    retry: RetryPolicy,
+}
+
+impl OllamaLlm {
+    pub fn new(client: Client, base_url: &str, model: &str) -> Self {
+        OllamaLlm {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        let body = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": stream,
+            "options": { "temperature": TEMPERATURE }
+        });
+
+        self.client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&body)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaLlm {
+    async fn complete(&self, messages: &[ChatMessage]) -> anyhow::Result<String> {
+        let res = retry::send_with_retry(self.request(messages, false), &self.retry).await?;
+        let status = res.status();
+        let text = res.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Ollama chat failed: HTTP {} - {}", status, text);
+        }
+
+        let value: serde_json::Value = serde_json::from_str(&text)?;
+        Ok(value["message"]["content"]
+            .as_str()
+            .unwrap_or("No answer generated")
+            .to_string())
+    }
+
+    /// Ollama streams one JSON object per line rather than server-sent events.
+    async fn complete_stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> anyhow::Result<String> {
+        let res = retry::send_with_retry(self.request(messages, true), &self.retry).await?;
+        let status = res.status();
+        if !status.is_success() {
+            anyhow::bail!("Ollama chat failed: HTTP {} - {}", status, res.text().await?);
+        }
+
+        let mut answer = String::new();
+        super::for_each_line(res, |line| {
+            if line.is_empty() {
+                return Ok(());
+            }
+            let value: serde_json::Value = serde_json::from_str(line)?;
+            if let Some(error) = value["error"].as_str() {
+                anyhow::bail!("Ollama chat failed: {}", error);
+            }
+            if let Some(token) = value["message"]["content"].as_str() {
+                answer.push_str(token);
+                on_token(token);
+            }
+            Ok(())
+        })
+        .await?;
+
+        Ok(answer)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
@@ -0,0 +1,101 @@
+use super::{ChatMessage, LlmProvider, TEMPERATURE};
+use crate::retry::{self, RetryPolicy};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Chat completions from OpenAI or any server exposing `/chat/completions`.
+pub struct OpenAiLlm {
+    client: Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    retry: RetryPolicy,
+}
+
+impl OpenAiLlm {
+    pub fn new(client: Client, base_url: &str, api_key: Option<String>, model: &str) -> Self {
+        OpenAiLlm {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        let body = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": TEMPERATURE,
+            "stream": stream
+        });
+
+        let request = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&body);
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiLlm {
+    async fn complete(&self, messages: &[ChatMessage]) -> anyhow::Result<String> {
+        let res = retry::send_with_retry(self.request(messages, false), &self.retry).await?;
+        let status = res.status();
+        let text = res.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Chat completion failed: HTTP {} - {}", status, text);
+        }
+
+        let value: serde_json::Value = serde_json::from_str(&text)?;
+        Ok(value["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap_or("No answer generated")
+            .to_string())
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> anyhow::Result<String> {
+        let res = retry::send_with_retry(self.request(messages, true), &self.retry).await?;
+        let status = res.status();
+        if !status.is_success() {
+            anyhow::bail!("Chat completion failed: HTTP {} - {}", status, res.text().await?);
+        }
+
+        let mut answer = String::new();
+        super::for_each_line(res, |line| {
+            let Some(data) = super::sse_data(line).filter(|d| *d != "[DONE]") else {
+                return Ok(());
+            };
+            let value: serde_json::Value = serde_json::from_str(data)?;
+            if let Some(token) = value["choices"][0]["delta"]["content"].as_str() {
+                answer.push_str(token);
+                on_token(token);
+            }
+            Ok(())
+        })
+        .await?;
+
+        Ok(answer)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
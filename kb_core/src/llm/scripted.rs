@@ -0,0 +1,46 @@
+use super::{ChatMessage, LlmProvider};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Fake provider replaying canned answers in order, for exercising the smart
+/// answer path without a network. Every conversation it receives is recorded.
+#[derive(Default)]
+pub struct ScriptedLlm {
+    responses: Mutex<VecDeque<String>>,
+    requests: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+impl ScriptedLlm {
+    pub fn new<I, S>(responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ScriptedLlm {
+            responses: Mutex::new(responses.into_iter().map(Into::into).collect()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Conversations received so far, oldest first.
+    pub fn requests(&self) -> Vec<Vec<ChatMessage>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ScriptedLlm {
+    async fn complete(&self, messages: &[ChatMessage]) -> anyhow::Result<String> {
+        self.requests.lock().unwrap().push(messages.to_vec());
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow::anyhow!("ScriptedLlm has no responses left"))
+    }
+
+    fn model(&self) -> &str {
+        "scripted"
+    }
+}
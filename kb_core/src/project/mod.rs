@@ -0,0 +1,320 @@
+use crate::config::{self, AppConfig};
+use crate::state::IndexState;
+use crate::utils;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Project used outside any git repository or `.kb-index.toml`. It keeps the
+/// original collection and state location so existing indexes stay usable.
+pub const DEFAULT_PROJECT: &str = "default";
+const PROJECT_FILE: &str = ".kb-index.toml";
+const PROJECTS_DIR: &str = "projects";
+const INFO_FILE: &str = "project.toml";
+
+/// Optional per-repository settings in `.kb-index.toml`.
+#[derive(Deserialize, Default)]
+struct ProjectFile {
+    name: Option<String>,
+}
+
+/// What `kb projects list` shows about a project.
+#[derive(Serialize, Deserialize)]
+pub struct ProjectInfo {
+    pub root: Option<PathBuf>,
+    pub collection: String,
+}
+
+/// A named set of indexed files with its own collection and state.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub name: String,
+    /// Directory the project was detected from, if any.
+    pub root: Option<PathBuf>,
+}
+
+impl Project {
+    /// Uses `explicit` when given, otherwise detects the project containing `start`.
+    ///
+    /// An explicit project keeps the root it was registered with, so its files
+    /// resolve the same from anywhere. A detected one fails if its name was
+    /// registered with a different root.
+    pub fn resolve(explicit: Option<&str>, start: &Path) -> anyhow::Result<Self> {
+        let detected = Self::detect(start)?;
+        match explicit {
+            Some(name) => {
+                let mut project = Self::named(name)?;
+                project.root = match project.info()? {
+                    Some(info) => info.root,
+                    None => detected.root,
+                };
+                Ok(project)
+            }
+            None => {
+                detected.check_root()?;
+                Ok(detected)
+            }
+        }
+    }
+
+    /// A project known only by name, such as one given to `kb projects remove`.
+    pub fn named(name: &str) -> anyhow::Result<Self> {
+        validate_name(name)?;
+        Ok(Project { name: name.to_string(), root: None })
+    }
+
+    /// Walks up from `start` to the nearest `.kb-index.toml` or git root.
+    ///
+    /// A `.kb-index.toml` may set `name`; otherwise the project is named after
+    /// its directory. Outside either, the default project is used.
+    pub fn detect(start: &Path) -> anyhow::Result<Self> {
+        let start = start.canonicalize()?;
+        let start = if start.is_file() {
+            start.parent().map(Path::to_path_buf).unwrap_or(start)
+        } else {
+            start
+        };
+
+        for dir in start.ancestors() {
+            let project_file = dir.join(PROJECT_FILE);
+            if project_file.exists() {
+                let settings: ProjectFile = toml::from_str(&fs::read_to_string(&project_file)?)?;
+                let name = match settings.name {
+                    Some(name) => {
+                        validate_name(&name)?;
+                        name
+                    }
+                    None => dir_name(dir),
+                };
+                return Ok(Project { name, root: Some(dir.to_path_buf()) });
+            }
+
+            if dir.join(".git").exists() {
+                return Ok(Project { name: dir_name(dir), root: Some(dir.to_path_buf()) });
+            }
+        }
+
+        Ok(Project {
+            name: DEFAULT_PROJECT.to_string(),
+            root: None,
+        })
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.name == DEFAULT_PROJECT
+    }
+
+    /// Vector store collection holding this project's chunks.
+    pub fn collection(&self, config: &AppConfig) -> String {
+        if self.is_default() {
+            config.chroma_collection().to_string()
+        } else {
+            format!("{}_{}", config.chroma_collection(), self.name)
+        }
+    }
+
+    /// Directory holding this project's `state.db` and lock files. Fails for
+    /// projects that haven't been indexed, rather than creating an empty one.
+    pub fn state_dir(&self) -> anyhow::Result<PathBuf> {
+        let dir = self.state_path()?;
+        if !dir.exists() {
+            let mut message = format!("Project '{}' hasn't been indexed yet. Run `kb index` first", self.name);
+            if let Some(hint) = default_index_hint()? {
+                message.push_str(&format!("\n\n{}", hint));
+            }
+            anyhow::bail!(message);
+        }
+        Ok(dir)
+    }
+
+    /// Like `state_dir`, creating the directory of a project indexed for the first time.
+    pub fn create_state_dir(&self) -> anyhow::Result<PathBuf> {
+        let dir = self.state_path()?;
+        if !dir.exists() {
+            if let Some(hint) = default_index_hint()? {
+                println!("ℹ️ Creating project '{}'. {}", self.name, hint);
+            }
+            fs::create_dir_all(&dir)?;
+        }
+        Ok(dir)
+    }
+
+    fn state_path(&self) -> anyhow::Result<PathBuf> {
+        let config_dir = config::get_config_dir()?;
+        if self.is_default() {
+            Ok(config_dir)
+        } else {
+            Ok(config_dir.join(PROJECTS_DIR).join(&self.name))
+        }
+    }
+
+    /// How a file is recorded in the index and the vector store: relative to
+    /// the project root, or absolute when the project has none or the file is
+    /// outside it. The same file gets the same path whichever directory
+    /// `kb` runs from.
+    pub fn index_path(&self, path: &Path) -> anyhow::Result<String> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", path.display()))?;
+        let relative = self.root.as_deref().and_then(|root| path.strip_prefix(root).ok());
+        Ok(relative.unwrap_or(&path).to_string_lossy().to_string())
+    }
+
+    /// Where a file recorded by `index_path` is on disk.
+    pub fn file_path(&self, indexed: &str) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join(indexed),
+            None => PathBuf::from(indexed),
+        }
+    }
+
+    /// Fails when the project's name is registered with another root, so two
+    /// repositories with the same directory name don't share an index. The
+    /// default project is shared on purpose.
+    fn check_root(&self) -> anyhow::Result<()> {
+        if self.is_default() {
+            return Ok(());
+        }
+        let registered = self.info()?.and_then(|info| info.root);
+        if let (Some(registered), Some(root)) = (registered, &self.root) {
+            if &registered != root {
+                anyhow::bail!(
+                    "Project '{}' is already indexed from {}. To index {} separately, give it another name \
+                     with `name = \"...\"` in a {} at its root. If the repository moved, run \
+                     `kb projects remove {}` and index it again",
+                    self.name,
+                    registered.display(),
+                    root.display(),
+                    PROJECT_FILE,
+                    self.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// What `register` recorded about the project, if it has been indexed.
+    fn info(&self) -> anyhow::Result<Option<ProjectInfo>> {
+        let path = self.state_path()?.join(INFO_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&fs::read_to_string(&path)?)?))
+    }
+
+    /// Records the project so `kb projects list` can find it.
+    pub fn register(&self, config: &AppConfig) -> anyhow::Result<()> {
+        let info = ProjectInfo {
+            root: self.root.clone(),
+            collection: self.collection(config),
+        };
+        utils::write_atomic(&self.state_dir()?.join(INFO_FILE), toml::to_string_pretty(&info)?.as_bytes())
+    }
+
+    /// Every registered project, sorted by name.
+    pub fn list() -> anyhow::Result<Vec<(String, ProjectInfo)>> {
+        let config_dir = config::get_config_dir()?;
+        let mut projects = Vec::new();
+
+        for dir in [config_dir.clone()].into_iter().chain(project_dirs(&config_dir)?) {
+            let info_path = dir.join(INFO_FILE);
+            if !info_path.exists() {
+                continue;
+            }
+            let info: ProjectInfo = toml::from_str(&fs::read_to_string(&info_path)?)?;
+            let name = if dir == config_dir {
+                DEFAULT_PROJECT.to_string()
+            } else {
+                dir.file_name().unwrap_or_default().to_string_lossy().to_string()
+            };
+            projects.push((name, info));
+        }
+
+        projects.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(projects)
+    }
+
+    /// Deletes the project's state. The caller removes its collection first.
+    pub fn remove_state(&self) -> anyhow::Result<()> {
+        let dir = self.state_dir()?;
+        if !self.is_default() {
+            fs::remove_dir_all(&dir)?;
+            return Ok(());
+        }
+
+        // The default project shares the config directory, so only remove its own files
+        for file in [INFO_FILE, "state.db", "state.db-wal", "state.db-shm"] {
+            let path = dir.join(file);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Points to the default project when it holds an index, which is where
+/// indexes built before projects existed live.
+fn default_index_hint() -> anyhow::Result<Option<String>> {
+    if !IndexState::exists(&config::get_config_dir()?)? {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "Indexes built before kb kept one per repository are in the '{}' project: \
+         pass `--project {}`, or add `name = \"{}\"` to a {} at the repository root to keep using it.",
+        DEFAULT_PROJECT, DEFAULT_PROJECT, DEFAULT_PROJECT, PROJECT_FILE
+    )))
+}
+
+fn project_dirs(config_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let dir = config_dir.join(PROJECTS_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Directory name made safe for a Chroma collection name.
+fn dir_name(dir: &Path) -> String {
+    let name: String = dir
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or(DEFAULT_PROJECT)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c.to_ascii_lowercase() } else { '-' })
+        .take(48)
+        .collect();
+
+    let name = name.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    if name.is_empty() {
+        DEFAULT_PROJECT.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Project names become directory and collection names, so keep them simple.
+fn validate_name(name: &str) -> anyhow::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 48
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric());
+
+    if !valid {
+        anyhow::bail!(
+            "Invalid project name '{}'. Use up to 48 letters, digits, '-' or '_', starting and ending with a letter or digit",
+            name
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use crate::config::AppConfig;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_BASE_DELAY_MS: u64 = 500;
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often and how long to wait before retrying a failed HTTP request.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        RetryPolicy {
+            max_attempts: config.retry_max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            base_delay: Duration::from_millis(config.retry_base_delay_ms.unwrap_or(DEFAULT_BASE_DELAY_MS)),
+            jitter: config.retry_jitter.unwrap_or(true),
+        }
+    }
+
+    /// Exponential backoff for the given 1-based attempt, capped at a minute.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(MAX_DELAY);
+
+        if self.jitter {
+            // Pick uniformly from the upper half so retries from concurrent batches spread out
+            delay / 2 + delay.mul_f64(fastrand::f64() / 2.0)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Sends a request, retrying connection failures, timeouts, 429s and 5xx responses.
+///
+/// Rate-limited responses wait for `Retry-After` or the `x-ratelimit-reset-*`
+/// headers when present. The last response is returned as-is once attempts are
+/// exhausted so callers can report the server's error body.
+pub async fn send_with_retry(request: RequestBuilder, policy: &RetryPolicy) -> anyhow::Result<Response> {
+    let mut attempt = 1;
+
+    loop {
+        // Streaming bodies can't be cloned, so they only get a single attempt
+        let Some(current) = request.try_clone() else {
+            return Ok(request.send().await?);
+        };
+        let is_last = attempt >= policy.max_attempts;
+
+        let delay = match current.send().await {
+            Ok(resp) if is_last || !is_retryable(resp.status()) => return Ok(resp),
+            Ok(resp) => server_delay(resp.headers()).unwrap_or_else(|| policy.backoff(attempt)),
+            Err(e) if is_last || !(e.is_connect() || e.is_timeout()) => return Err(e.into()),
+            Err(_) => policy.backoff(attempt),
+        };
+
+        tokio::time::sleep(delay.min(MAX_DELAY)).await;
+        attempt += 1;
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Wait requested by the server, from `Retry-After` or OpenAI-style rate-limit headers.
+fn server_delay(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(value) = headers.get(RETRY_AFTER).and_then(|v| v.to_str().ok()) {
+        if let Ok(seconds) = value.trim().parse::<f64>() {
+            return Some(Duration::from_secs_f64(seconds.max(0.0)));
+        }
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            let wait = date.signed_duration_since(chrono::Utc::now());
+            return Some(wait.to_std().unwrap_or_default());
+        }
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok())
+        .filter_map(parse_reset_duration)
+        .max()
+}
+
+/// Parses durations like `1s`, `250ms` or `6m0s`.
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .filter(|&i| i > 0)?;
+        let number: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_len] {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return None,
+        };
+        total += Duration::from_secs_f64(seconds);
+        rest = &rest[unit_len..];
+    }
+
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_reset_durations() {
+        assert_eq!(parse_reset_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset_duration("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_reset_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset_duration(" 2s "), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn rejects_malformed_reset_durations() {
+        for value in ["s", "10", "5x", "1d", "ms5"] {
+            assert_eq!(parse_reset_duration(value), None, "{}", value);
+        }
+    }
+}
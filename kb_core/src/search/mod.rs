@@ -0,0 +1,388 @@
+use crate::chunking;
+use crate::project::Project;
+use crate::state::{IndexState, IndexedChunk};
+use crate::store::{MetadataFilter, QueryHit};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use grep_regex::RegexMatcherBuilder;
+use grep_searcher::sinks::UTF8;
+use grep_searcher::Searcher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// BM25 term-frequency saturation and length normalization.
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+/// Reciprocal rank fusion constant; dampens the weight of the very top ranks.
+const RRF_K: f64 = 60.0;
+/// Candidates taken from each ranking per requested result before fusing.
+const FUSION_DEPTH: usize = 4;
+/// Weight of matches on a chunk's first line, where its signature or heading sits,
+/// so the definition of an identifier outranks its call sites.
+const FIRST_LINE_WEIGHT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    Semantic,
+    Lexical,
+    Hybrid,
+}
+
+impl SearchMode {
+    pub fn parse(mode: &str) -> anyhow::Result<Self> {
+        match mode {
+            "semantic" => Ok(SearchMode::Semantic),
+            "lexical" => Ok(SearchMode::Lexical),
+            "hybrid" => Ok(SearchMode::Hybrid),
+            other => anyhow::bail!(
+                "Unknown search mode '{}'. Expected one of: semantic, lexical, hybrid",
+                other
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchMode::Semantic => "semantic",
+            SearchMode::Lexical => "lexical",
+            SearchMode::Hybrid => "hybrid",
+        }
+    }
+
+    pub fn uses_embeddings(self) -> bool {
+        self != SearchMode::Lexical
+    }
+
+    /// How many hits to request from each ranking so fusion has enough to choose from.
+    pub fn candidates(self, top_k: usize) -> usize {
+        match self {
+            SearchMode::Hybrid => top_k * FUSION_DEPTH,
+            _ => top_k,
+        }
+    }
+}
+
+/// Builds a query filter from command-line options.
+///
+/// Path globs are matched against the files in the index state, as they were
+/// indexed and, for absolute paths, relative to the current directory. The
+/// matches are sent to the store as a list of sources. A pattern without wildcards also matches
+/// everything below it. `since` is a duration like `7d` or `12h`, or a date.
+pub fn build_filter(
+    project: &Project,
+    indexed: &[String],
+    paths: &[String],
+    exclude: &[String],
+    languages: &[String],
+    since: Option<&str>,
+) -> anyhow::Result<MetadataFilter> {
+    let cwd = std::env::current_dir()?;
+    let matching = |patterns: &[String]| -> anyhow::Result<Vec<String>> {
+        let globs = glob_set(patterns)?;
+        let mut files: Vec<String> = indexed
+            .iter()
+            .filter(|file| path_forms(&project.file_path(file), file, &cwd).iter().any(|p| globs.is_match(p)))
+            .cloned()
+            .collect();
+        files.sort();
+        Ok(files)
+    };
+
+    let mut sources = if paths.is_empty() { None } else { Some(matching(paths)?) };
+    let mut excluded_sources = if exclude.is_empty() { Vec::new() } else { matching(exclude)? };
+
+    // Stores may send the file lists with the query, so name the files left out
+    // when the globs match most of the index
+    if let Some(matched) = sources.take_if(|m| !m.is_empty() && m.len() * 2 > indexed.len()) {
+        let matched: HashSet<&String> = matched.iter().collect();
+        excluded_sources.extend(indexed.iter().filter(|f| !matched.contains(f)).cloned());
+        excluded_sources.sort();
+        excluded_sources.dedup();
+    }
+
+    Ok(MetadataFilter {
+        sources,
+        excluded_sources,
+        languages: languages.iter().map(|l| chunking::language_name(l)).collect(),
+        modified_since: since.map(parse_since).transpose()?,
+    })
+}
+
+fn glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        builder.add(Glob::new(pattern)?);
+        if !pattern.contains(['*', '?', '[', '{']) {
+            builder.add(Glob::new(&format!("{}/**", pattern))?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// The indexed path without a leading `./`, plus the form relative to `cwd` of
+/// `path`, where the file is on disk.
+fn path_forms(path: &Path, file: &str, cwd: &Path) -> Vec<String> {
+    let mut forms = vec![file.trim_start_matches("./").to_string()];
+    let absolute = if path.is_absolute() { path.to_path_buf() } else { cwd.join(path) };
+    if let Ok(relative) = absolute.strip_prefix(cwd) {
+        forms.push(relative.to_string_lossy().trim_start_matches("./").to_string());
+    }
+    forms
+}
+
+/// Parses `30m`, `12h`, `7d` or `2w` as a time that long ago, or a
+/// `YYYY-MM-DD` date, into a Unix timestamp.
+fn parse_since(since: &str) -> anyhow::Result<u64> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        let start = date.and_hms_opt(0, 0, 0).unwrap().and_local_timezone(chrono::Local);
+        if let Some(start) = start.earliest() {
+            return Ok(start.timestamp().max(0) as u64);
+        }
+    }
+
+    let (amount, unit) = since.split_at(since.len().saturating_sub(1));
+    let seconds = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => 0,
+    };
+    let Some(amount) = amount.parse::<u64>().ok().filter(|_| seconds > 0) else {
+        anyhow::bail!("Invalid --since '{}'. Use a duration like 30m, 12h, 7d or 2w, or a date like 2025-01-31", since);
+    };
+
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    Ok(now.saturating_sub(amount * seconds))
+}
+
+/// Chunks ranked by [`lexical_search`].
+#[derive(Debug, Default)]
+pub struct LexicalResults {
+    pub hits: Vec<QueryHit>,
+    /// Indexed files that couldn't be read, most likely deleted since indexing.
+    pub missing: Vec<String>,
+}
+
+/// Ranks indexed chunks against the query terms with BM25.
+///
+/// Files are read from `project`'s root and scanned with ripgrep's searcher,
+/// and matching lines are mapped back to chunks through the line ranges
+/// recorded in the index state. Files that `filter` rules out are skipped;
+/// those that can't be read are listed in [`LexicalResults::missing`].
+pub fn lexical_search(
+    state: &IndexState,
+    project: &Project,
+    query: &str,
+    top_k: usize,
+    filter: &MetadataFilter,
+) -> anyhow::Result<LexicalResults> {
+    let terms = query_terms(query);
+    if terms.is_empty() {
+        return Ok(LexicalResults::default());
+    }
+
+    let pattern = terms
+        .iter()
+        .map(|t| regex::escape(t))
+        .collect::<Vec<_>>()
+        .join("|");
+    let matcher = RegexMatcherBuilder::new()
+        .case_insensitive(true)
+        .build(&pattern)?;
+
+    let chunk_count = state.files.values().map(|f| f.chunks.len()).sum::<usize>();
+    if chunk_count == 0 {
+        return Ok(LexicalResults::default());
+    }
+    let avg_len = state
+        .files
+        .values()
+        .flat_map(|f| &f.chunks)
+        .map(chunk_len)
+        .sum::<f64>()
+        / chunk_count as f64;
+
+    // Term frequencies per chunk, keyed by (file, chunk index)
+    let mut frequencies: HashMap<(&str, usize), Vec<usize>> = HashMap::new();
+    let mut missing = Vec::new();
+
+    for (file, meta) in &state.files {
+        let extension = Path::new(file).extension().and_then(|s| s.to_str()).unwrap_or_default();
+        let language = chunking::language_name(extension);
+        if !filter.matches(file, Some(&language), Some(meta.last_modified)) {
+            continue;
+        }
+
+        let mut matched_lines: Vec<(usize, String)> = Vec::new();
+        let searched = Searcher::new().search_path(
+            &matcher,
+            project.file_path(file),
+            UTF8(|line_number, line| {
+                matched_lines.push((line_number as usize, line.to_lowercase()));
+                Ok(true)
+            }),
+        );
+        if searched.is_err() {
+            missing.push(file.clone());
+            continue;
+        }
+
+        for (line_number, line) in matched_lines {
+            let counts: Vec<usize> = terms.iter().map(|t| line.matches(t.as_str()).count()).collect();
+
+            for (i, chunk) in meta.chunks.iter().enumerate() {
+                if chunk.start_line == 0 || line_number < chunk.start_line || line_number > chunk.end_line {
+                    continue;
+                }
+                let tf = frequencies
+                    .entry((file.as_str(), i))
+                    .or_insert_with(|| vec![0; terms.len()]);
+                let weight = if line_number == chunk.start_line { FIRST_LINE_WEIGHT } else { 1 };
+                for (total, count) in tf.iter_mut().zip(&counts) {
+                    *total += count * weight;
+                }
+            }
+        }
+    }
+
+    let doc_freq: Vec<usize> = (0..terms.len())
+        .map(|t| frequencies.values().filter(|tf| tf[t] > 0).count())
+        .collect();
+    let idf: Vec<f64> = doc_freq
+        .iter()
+        .map(|&df| ((chunk_count as f64 - df as f64 + 0.5) / (df as f64 + 0.5) + 1.0).ln())
+        .collect();
+
+    let mut scored: Vec<(f64, &str, &IndexedChunk)> = frequencies
+        .iter()
+        .map(|(&(file, i), tf)| {
+            let chunk = &state.files[file].chunks[i];
+            let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * chunk_len(chunk) / avg_len);
+            let score = tf
+                .iter()
+                .zip(&idf)
+                .map(|(&f, idf)| idf * (f as f64 * (BM25_K1 + 1.0)) / (f as f64 + norm))
+                .sum::<f64>();
+            (score, file, chunk)
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut contents: HashMap<&str, String> = HashMap::new();
+    let mut hits = Vec::new();
+    for (score, file, chunk) in scored {
+        if hits.len() == top_k {
+            break;
+        }
+        if !contents.contains_key(file) {
+            let Ok(text) = fs::read_to_string(project.file_path(file)) else {
+                missing.push(file.to_string());
+                continue;
+            };
+            contents.insert(file, text);
+        }
+        let Some(document) = contents[file].get(chunk.start_byte..chunk.end_byte) else {
+            continue;
+        };
+
+        hits.push(QueryHit {
+            id: chunk.id.clone(),
+            document: document.to_string(),
+            metadata: serde_json::json!({
+                "source": file,
+                "start_line": chunk.start_line,
+                "end_line": chunk.end_line,
+                "start_byte": chunk.start_byte,
+                "end_byte": chunk.end_byte,
+            }),
+            distance: None,
+            score: Some(score),
+        });
+    }
+
+    missing.sort();
+    missing.dedup();
+    Ok(LexicalResults { hits, missing })
+}
+
+/// Merges several rankings with reciprocal rank fusion.
+///
+/// Hits are matched by chunk ID. When a chunk appears in more than one ranking
+/// the first occurrence is kept, so pass the semantic ranking first to keep its
+/// distance and breadcrumb.
+pub fn fuse(rankings: Vec<Vec<QueryHit>>, top_k: usize) -> Vec<QueryHit> {
+    let mut fused: Vec<QueryHit> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for ranking in rankings {
+        for (rank, mut hit) in ranking.into_iter().enumerate() {
+            let contribution = 1.0 / (RRF_K + rank as f64 + 1.0);
+            match positions.get(&hit.id) {
+                Some(&i) => *fused[i].score.get_or_insert(0.0) += contribution,
+                None => {
+                    hit.score = Some(contribution);
+                    positions.insert(hit.id.clone(), fused.len());
+                    fused.push(hit);
+                }
+            }
+        }
+    }
+
+    fused.sort_by(|a, b| b.score.unwrap_or_default().total_cmp(&a.score.unwrap_or_default()));
+    fused.truncate(top_k);
+    fused
+}
+
+/// Splits a query into lowercase identifier-like terms, keeping `snake_case` intact.
+fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for term in query.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        let term = term.to_lowercase();
+        if term.chars().count() >= 2 && !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// Chunk length in bytes, for BM25 length normalization.
+fn chunk_len(chunk: &IndexedChunk) -> f64 {
+    chunk.end_byte.saturating_sub(chunk.start_byte).max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_since_takes_durations_back_from_now() {
+        let now = chrono::Utc::now().timestamp() as u64;
+        for (since, seconds) in [("30m", 30 * 60), ("12h", 12 * 3600), ("7d", 7 * 86400), ("2w", 14 * 86400)] {
+            let parsed = parse_since(since).unwrap();
+            assert!(now - seconds <= parsed && parsed <= now - seconds + 5, "{}", since);
+        }
+    }
+
+    #[test]
+    fn parse_since_takes_dates_at_local_midnight() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2025, 1, 31)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_local_timezone(chrono::Local)
+            .earliest()
+            .unwrap()
+            .timestamp() as u64;
+        assert_eq!(parse_since("2025-01-31").unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_since_rejects_other_input() {
+        for since in ["", "d", "7", "7y", "-1d", "2025-13-01", "last week"] {
+            assert!(parse_since(since).is_err(), "{}", since);
+        }
+    }
+}
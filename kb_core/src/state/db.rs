@@ -0,0 +1,405 @@
+use super::{CachedAnswer, FileMetadata, IndexState, IndexedChunk, QueryCache, SessionManager, SessionState};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DB_FILE: &str = "state.db";
+const INDEX_STATE_FILE: &str = "index-state.json";
+const QUERY_CACHE_FILE: &str = "query-cache.json";
+const SESSIONS_FILE: &str = "sessions.json";
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many
+/// have run, so append new ones rather than editing old ones.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE files (
+        path TEXT PRIMARY KEY,
+        last_modified INTEGER NOT NULL
+    );
+    CREATE TABLE chunks (
+        path TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        id TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        start_line INTEGER NOT NULL,
+        end_line INTEGER NOT NULL,
+        start_byte INTEGER NOT NULL,
+        end_byte INTEGER NOT NULL,
+        PRIMARY KEY (path, position)
+    );
+    CREATE TABLE query_cache (
+        id INTEGER PRIMARY KEY,
+        query TEXT NOT NULL,
+        context_hash TEXT NOT NULL,
+        embedding BLOB NOT NULL,
+        answer TEXT NOT NULL
+    );
+    CREATE INDEX query_cache_lookup ON query_cache (query, context_hash);
+    CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        last_updated INTEGER NOT NULL
+    );
+    CREATE TABLE session_turns (
+        session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        query TEXT NOT NULL,
+        response TEXT NOT NULL,
+        PRIMARY KEY (session_id, position)
+    );
+    CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+", "
+    ALTER TABLE sessions ADD COLUMN summary TEXT;
+    ALTER TABLE sessions ADD COLUMN summarized INTEGER NOT NULL DEFAULT 0;
+", "
+    ALTER TABLE query_cache ADD COLUMN scope TEXT;
+", "
+    ALTER TABLE query_cache ADD COLUMN sources TEXT;
+", "
+    -- Forget modification times so the next `kb index` rewrites every chunk's
+    -- metadata, recording the language and time of chunks indexed before them
+    UPDATE files SET last_modified = 0;
+"];
+
+/// Whether `config_dir` holds indexed files, in the database or in the JSON
+/// file used before it.
+pub fn has_index(config_dir: &Path) -> Result<bool> {
+    if config_dir.join(INDEX_STATE_FILE).exists() {
+        return Ok(true);
+    }
+    if !config_dir.join(DB_FILE).exists() {
+        return Ok(false);
+    }
+    let conn = open(config_dir)?;
+    let files: i64 = conn.query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?;
+    Ok(files > 0)
+}
+
+/// Opens the state database, creating or upgrading its schema as needed.
+pub fn open(config_dir: &Path) -> Result<Connection> {
+    let path = config_dir.join(DB_FILE);
+    let mut conn = Connection::open(&path)
+        .with_context(|| format!("Failed to open state database at {}", path.display()))?;
+    conn.busy_timeout(Duration::from_secs(30))?;
+    conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+    migrate(&mut conn, config_dir)?;
+    Ok(conn)
+}
+
+fn migrate(conn: &mut Connection, config_dir: &Path) -> Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = tx.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as usize;
+
+    if version > MIGRATIONS.len() {
+        anyhow::bail!(
+            "State database schema {} is newer than this build of kb supports ({})",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    for migration in &MIGRATIONS[version..] {
+        tx.execute_batch(migration)?;
+    }
+    let imported = if version == 0 { import_json(&tx, config_dir)? } else { Vec::new() };
+    tx.pragma_update(None, "user_version", MIGRATIONS.len() as i64)?;
+    tx.commit()?;
+
+    // Keep the old files around under a new name in case anything needs checking
+    for path in imported {
+        fs::rename(&path, path.with_extension("json.migrated"))?;
+    }
+
+    Ok(())
+}
+
+/// Imports the JSON state files used before the database, returning the ones found.
+fn import_json(conn: &Connection, config_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut imported = Vec::new();
+
+    let path = config_dir.join(INDEX_STATE_FILE);
+    if let Some(state) = read_json::<IndexState>(&path)? {
+        let paths: HashSet<String> = state.files.keys().cloned().collect();
+        save_files(conn, &state.files, &paths)?;
+        // Its chunks lack languages and modification times as well
+        conn.execute("UPDATE files SET last_modified = 0", [])?;
+        imported.push(path);
+    }
+
+    let path = config_dir.join(QUERY_CACHE_FILE);
+    if let Some(cache) = read_json::<JsonQueryCache>(&path)? {
+        insert_query_cache(conn, &cache.entries)?;
+        imported.push(path);
+    }
+
+    let path = config_dir.join(SESSIONS_FILE);
+    if let Some(manager) = read_json::<SessionManager>(&path)? {
+        for session in manager.sessions.values() {
+            save_session(conn, session)?;
+        }
+        save_active_session(conn, manager.active_session.as_deref())?;
+        imported.push(path);
+    }
+
+    Ok(imported)
+}
+
+/// Layout of the query cache file used before the database.
+#[derive(serde::Deserialize)]
+struct JsonQueryCache {
+    entries: Vec<QueryCache>,
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {} for migration", path.display()))?;
+    let value = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse {} for migration", path.display()))?;
+    Ok(Some(value))
+}
+
+/// Paths of the indexed files, without their chunks.
+pub fn load_paths(conn: &Connection) -> Result<Vec<String>> {
+    let paths = conn
+        .prepare("SELECT path FROM files")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(paths)
+}
+
+pub fn load_files(conn: &Connection) -> Result<HashMap<String, FileMetadata>> {
+    let mut files: HashMap<String, FileMetadata> = conn
+        .prepare("SELECT path, last_modified FROM files")?
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                FileMetadata { last_modified: row.get::<_, i64>(1)? as u64, chunks: Vec::new() },
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT path, id, hash, start_line, end_line, start_byte, end_byte
+         FROM chunks ORDER BY path, position",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        if let Some(file) = files.get_mut(&path) {
+            file.chunks.push(IndexedChunk {
+                id: row.get(1)?,
+                hash: row.get(2)?,
+                start_line: row.get::<_, i64>(3)? as usize,
+                end_line: row.get::<_, i64>(4)? as usize,
+                start_byte: row.get::<_, i64>(5)? as usize,
+                end_byte: row.get::<_, i64>(6)? as usize,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Rewrites the given paths, deleting those no longer in `files`.
+pub fn save_files(conn: &Connection, files: &HashMap<String, FileMetadata>, paths: &HashSet<String>) -> Result<()> {
+    let mut delete = conn.prepare("DELETE FROM files WHERE path = ?1")?;
+    let mut insert_file = conn.prepare("INSERT INTO files (path, last_modified) VALUES (?1, ?2)")?;
+    let mut insert_chunk = conn.prepare(
+        "INSERT INTO chunks (path, position, id, hash, start_line, end_line, start_byte, end_byte)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+
+    for path in paths {
+        delete.execute([path])?;
+        let Some(file) = files.get(path) else { continue };
+
+        insert_file.execute(params![path, file.last_modified as i64])?;
+        for (position, chunk) in file.chunks.iter().enumerate() {
+            insert_chunk.execute(params![
+                path,
+                position as i64,
+                chunk.id,
+                chunk.hash,
+                chunk.start_line as i64,
+                chunk.end_line as i64,
+                chunk.start_byte as i64,
+                chunk.end_byte as i64,
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The most recent answer cached for exactly this query and context.
+pub fn find_cached_answer(conn: &Connection, query: &str, context_hash: &str) -> Result<Option<String>> {
+    let answer = conn
+        .query_row(
+            "SELECT answer FROM query_cache WHERE query = ?1 AND context_hash = ?2 ORDER BY id DESC LIMIT 1",
+            params![query, context_hash],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(answer)
+}
+
+/// IDs and embeddings of the latest `limit` entries in `scope` with
+/// `dimension`-sized embeddings.
+pub fn recent_cache_embeddings(
+    conn: &Connection,
+    scope: &str,
+    dimension: usize,
+    limit: usize,
+) -> Result<Vec<(i64, Vec<f32>)>> {
+    let entries = conn
+        .prepare(
+            "SELECT id, embedding FROM query_cache WHERE scope = ?1 AND length(embedding) = ?2
+             ORDER BY id DESC LIMIT ?3",
+        )?
+        .query_map(params![scope, (dimension * 4) as i64, limit as i64], |row| {
+            Ok((row.get(0)?, decode_embedding(&row.get::<_, Vec<u8>>(1)?)))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(entries)
+}
+
+pub fn load_cached_answer(conn: &Connection, id: i64) -> Result<CachedAnswer> {
+    let (answer, sources): (String, Option<String>) = conn.query_row(
+        "SELECT answer, sources FROM query_cache WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let sources = match sources {
+        Some(sources) => serde_json::from_str(&sources)?,
+        None => Vec::new(),
+    };
+    Ok(CachedAnswer { answer, sources })
+}
+
+pub fn insert_query_cache(conn: &Connection, entries: &[QueryCache]) -> Result<()> {
+    let mut insert = conn.prepare(
+        "INSERT INTO query_cache (query, context_hash, embedding, scope, answer, sources)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for entry in entries {
+        insert.execute(params![
+            entry.query,
+            entry.context_hash,
+            encode_embedding(&entry.embedding),
+            entry.scope,
+            entry.answer,
+            (!entry.sources.is_empty()).then(|| serde_json::to_string(&entry.sources)).transpose()?,
+        ])?;
+    }
+    Ok(())
+}
+
+pub fn clear_query_cache(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM query_cache", [])?;
+    Ok(())
+}
+
+pub fn load_sessions(conn: &Connection) -> Result<SessionManager> {
+    let mut sessions: HashMap<String, SessionState> = conn
+        .prepare("SELECT id, created_at, last_updated, summary, summarized FROM sessions")?
+        .query_map([], |row| {
+            let id: String = row.get(0)?;
+            Ok((
+                id.clone(),
+                SessionState {
+                    id,
+                    queries: Vec::new(),
+                    responses: Vec::new(),
+                    created_at: row.get::<_, i64>(1)? as u64,
+                    last_updated: row.get::<_, i64>(2)? as u64,
+                    summary: row.get(3)?,
+                    summarized: row.get::<_, i64>(4)? as usize,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare("SELECT session_id, query, response FROM session_turns ORDER BY session_id, position")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: String = row.get(0)?;
+        if let Some(session) = sessions.get_mut(&id) {
+            session.queries.push(row.get(1)?);
+            session.responses.push(row.get(2)?);
+        }
+    }
+
+    let active_session = conn
+        .query_row("SELECT value FROM settings WHERE key = 'active_session'", [], |row| row.get(0))
+        .optional()?;
+
+    Ok(SessionManager { sessions, active_session, ..Default::default() })
+}
+
+/// Inserts or updates a session and appends the turns not stored yet.
+pub fn save_session(conn: &Connection, session: &SessionState) -> Result<()> {
+    conn.execute(
+        "INSERT INTO sessions (id, created_at, last_updated, summary, summarized) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (id) DO UPDATE SET
+             last_updated = excluded.last_updated,
+             summary = excluded.summary,
+             summarized = excluded.summarized",
+        params![
+            session.id,
+            session.created_at as i64,
+            session.last_updated as i64,
+            session.summary,
+            session.summarized as i64,
+        ],
+    )?;
+
+    let stored: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM session_turns WHERE session_id = ?1",
+        [&session.id],
+        |row| row.get(0),
+    )?;
+    let mut insert_turn = conn.prepare(
+        "INSERT INTO session_turns (session_id, position, query, response) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    let turns = session.queries.iter().zip(&session.responses).enumerate().skip(stored as usize);
+    for (position, (query, response)) in turns {
+        insert_turn.execute(params![session.id, position as i64, query, response])?;
+    }
+
+    Ok(())
+}
+
+pub fn delete_session(conn: &Connection, id: &str) -> Result<()> {
+    conn.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+pub fn save_active_session(conn: &Connection, id: Option<&str>) -> Result<()> {
+    conn.execute("DELETE FROM settings WHERE key = 'active_session'", [])?;
+    if let Some(id) = id {
+        conn.execute("INSERT INTO settings (key, value) VALUES ('active_session', ?1)", [id])?;
+    }
+    Ok(())
+}
+
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+mod db;
+
+use crate::store::QueryHit;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
-use std::io::Write;
+use std::path::Path;
 use std::time::{UNIX_EPOCH, SystemTime};
 use uuid::Uuid;
 
@@ -9,13 +11,57 @@ use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use anyhow::{Result, Context};
 
-const INDEX_STATE_FILE: &str = "index-state.json";
-const QUERY_CACHE_FILE: &str = "query-cache.json";
+/// Most recent cached answers compared against a new query's embedding.
+const SIMILARITY_CANDIDATES: usize = 500;
+
+/// Advisory lock held while mutating the index state or the session and query
+/// cache files, so concurrent `kb` processes don't overwrite each other.
+pub struct StateLock {
+    _file: fs::File,
+}
+
+impl StateLock {
+    pub fn index(config_dir: &Path) -> Result<Self> {
+        Self::acquire(&config_dir.join("index-state.lock"))
+    }
+
+    pub fn sessions(config_dir: &Path) -> Result<Self> {
+        Self::acquire(&config_dir.join("sessions.lock"))
+    }
+
+    fn acquire(path: &Path) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) => {
+                eprintln!("⏳ Waiting for another kb process to release {}", path.display());
+                file.lock()?;
+            }
+            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        Ok(StateLock { _file: file })
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IndexedChunk {
     pub hash: String,
     pub id: String,
+    #[serde(default)]
+    pub start_line: usize,
+    #[serde(default)]
+    pub end_line: usize,
+    #[serde(default)]
+    pub start_byte: usize,
+    #[serde(default)]
+    pub end_byte: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,28 +70,48 @@ pub struct FileMetadata {
     pub chunks: Vec<IndexedChunk>,
 }
 
+/// Indexed files and their chunks, stored in `state.db`.
+///
+/// Change files through `update_file_chunks` and `remove_file` so `save` knows
+/// which rows to rewrite.
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct IndexState {
     pub files: HashMap<String, FileMetadata>,
+    #[serde(skip)]
+    dirty: HashSet<String>,
 }
 
 impl IndexState {
-    pub fn load(config_dir: &PathBuf) -> Result<Self> {
-        let path = config_dir.join(INDEX_STATE_FILE);
-        if !path.exists() {
-            return Ok(IndexState::default());
-        }
-        let contents = fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read index state from {}", path.display()))?;
-        let state = serde_json::from_str(&contents)?;
-        Ok(state)
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let conn = db::open(config_dir)?;
+        Ok(IndexState {
+            files: db::load_files(&conn)?,
+            dirty: HashSet::new(),
+        })
+    }
+
+    /// Whether anything has been indexed with state in `config_dir`.
+    pub fn exists(config_dir: &Path) -> Result<bool> {
+        db::has_index(config_dir)
     }
 
-    pub fn save(&self, config_dir: &PathBuf) -> Result<()> {
-        let path = config_dir.join(INDEX_STATE_FILE);
-        let json = serde_json::to_string_pretty(self)?;
-        let mut file = fs::File::create(&path)?;
-        file.write_all(json.as_bytes())?;
+    /// Paths of the indexed files, for when their chunks aren't needed.
+    pub fn indexed_paths(config_dir: &Path) -> Result<Vec<String>> {
+        let conn = db::open(config_dir)?;
+        db::load_paths(&conn)
+    }
+
+    /// Writes the files changed since the last load or save in one transaction.
+    pub fn save(&mut self, config_dir: &Path) -> Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = db::open(config_dir)?;
+        let tx = conn.transaction()?;
+        db::save_files(&tx, &self.files, &self.dirty)?;
+        tx.commit()?;
+        self.dirty.clear();
         Ok(())
     }
 
@@ -58,6 +124,7 @@ impl IndexState {
     }
 
     pub fn update_file_chunks(&mut self, path: &str, chunks: Vec<IndexedChunk>, last_modified: u64) {
+        self.dirty.insert(path.to_string());
         self.files.insert(
             path.to_string(),
             FileMetadata {
@@ -67,15 +134,60 @@ impl IndexState {
         );
     }
 
+    pub fn remove_file(&mut self, path: &str) -> Option<FileMetadata> {
+        self.dirty.insert(path.to_string());
+        self.files.remove(path)
+    }
+
+    /// Rebuilds the state from chunk metadata kept in the vector store.
+    ///
+    /// Files get a zero timestamp so the next run re-reads them, keeping every
+    /// chunk whose hash still matches. Chunks indexed before hashes were stored
+    /// in the metadata are skipped.
+    pub fn from_records(records: &[(String, serde_json::Value)]) -> Self {
+        let mut state = IndexState::default();
+        for (id, metadata) in records {
+            let field = |name: &str| metadata.get(name).and_then(|v| v.as_u64()).unwrap_or_default() as usize;
+            let (Some(source), Some(hash)) = (
+                metadata.get("source").and_then(|v| v.as_str()),
+                metadata.get("hash").and_then(|v| v.as_str()),
+            ) else {
+                continue;
+            };
+
+            let file = state.files.entry(source.to_string()).or_insert_with(|| FileMetadata {
+                last_modified: 0,
+                chunks: Vec::new(),
+            });
+            file.chunks.push(IndexedChunk {
+                hash: hash.to_string(),
+                id: id.clone(),
+                start_line: field("start_line"),
+                end_line: field("end_line"),
+                start_byte: field("start_byte"),
+                end_byte: field("end_byte"),
+            });
+        }
+        state.dirty = state.files.keys().cloned().collect();
+        state
+    }
+
+    /// Deterministic ID for the `occurrence`-th chunk with this hash in a file,
+    /// so re-indexing the same content upserts instead of duplicating it.
+    pub fn chunk_id(project: &str, path: &str, hash: &str, occurrence: usize) -> String {
+        let mut hasher = Sha256::new();
+        for part in [project, path, hash, &occurrence.to_string()] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        hex::encode(&hasher.finalize()[..16])
+    }
+
     pub fn hash_chunk(content: &str) -> String {
         let mut hasher = Sha256::new();
         hasher.update(content.as_bytes());
         hex::encode(hasher.finalize())
     }
-
-    pub fn has_chunk(state: &[IndexedChunk], hash: &str) -> bool {
-        state.iter().any(|chunk| chunk.hash == hash)
-    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -83,66 +195,74 @@ pub struct QueryCache {
     pub query: String,
     pub context_hash: String,
     pub embedding: Vec<f32>,
+    /// Search mode of the unfiltered query this answers, which similar queries
+    /// in that mode may reuse. Filtered queries have none.
+    #[serde(default)]
+    pub scope: Option<String>,
     pub answer: String,
+    /// Chunks the answer was generated from, in the order its citations number them.
+    #[serde(default)]
+    pub sources: Vec<QueryHit>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// A cached answer with the chunks it cites. Entries cached before sources
+/// were recorded have none.
+pub struct CachedAnswer {
+    pub answer: String,
+    pub sources: Vec<QueryHit>,
+}
+
+/// Answers cached in `state.db`. Lookups query the database as needed rather
+/// than loading every entry up front.
 pub struct QueryState {
-    pub entries: Vec<QueryCache>,
+    conn: rusqlite::Connection,
 }
 
 impl QueryState {
-    pub fn load(config_dir: &PathBuf) -> Result<Self> {
-        let path = config_dir.join(QUERY_CACHE_FILE);
-        if !path.exists() {
-            return Ok(QueryState::default());
-        }
-        let contents = fs::read_to_string(&path)?;
-        let state = serde_json::from_str(&contents)?;
-        Ok(state)
+    pub fn open(config_dir: &Path) -> Result<Self> {
+        Ok(QueryState { conn: db::open(config_dir)? })
     }
 
-    pub fn save(&self, config_dir: &PathBuf) -> Result<()> {
-        let path = config_dir.join(QUERY_CACHE_FILE);
-        let json = serde_json::to_string_pretty(self)?;
-        let mut file = fs::File::create(&path)?;
-        file.write_all(json.as_bytes())?;
-        Ok(())
+    /// Drops every cached answer, e.g. after the embedding model changed.
+    pub fn clear(config_dir: &Path) -> Result<()> {
+        let conn = db::open(config_dir)?;
+        db::clear_query_cache(&conn)
     }
 
-    pub fn get_cached_answer(&self, query: &str, context_hash: &str) -> Option<String> {
-        self.entries.iter()
-            .find(|e| e.query == query && e.context_hash == context_hash)
-            .map(|e| e.answer.clone())
+    pub fn get_cached_answer(&self, query: &str, context_hash: &str) -> Result<Option<String>> {
+        db::find_cached_answer(&self.conn, query, context_hash)
     }
 
     pub fn insert_answer(
-        &mut self,
+        &self,
         query: String,
         context_hash: String,
         embedding: Vec<f32>,
-        answer: String
-    ) {
-        self.entries.push(QueryCache { query, context_hash, embedding, answer });
+        scope: Option<String>,
+        answer: String,
+        sources: Vec<QueryHit>,
+    ) -> Result<()> {
+        db::insert_query_cache(&self.conn, &[QueryCache { query, context_hash, embedding, scope, answer, sources }])
     }
 
-    pub fn find_similar(&self, query_embedding: &[f32], threshold: f32) -> Option<String> {
-        self.entries
-            .iter()
-            .filter_map(|e| {
-                if e.embedding.len() != query_embedding.len() {
-                    return None;
-                }
-
-                let similarity = cosine_similarity(&e.embedding, query_embedding);
-                if similarity > threshold {
-                    Some((similarity, e.answer.clone()))
-                } else {
-                    None
-                }
+    /// The answer to the most similar recent query in `scope` above `threshold`.
+    /// Only the latest `SIMILARITY_CANDIDATES` entries with a matching dimension
+    /// are compared.
+    pub fn find_similar(&self, query_embedding: &[f32], scope: &str, threshold: f32) -> Result<Option<CachedAnswer>> {
+        let candidates =
+            db::recent_cache_embeddings(&self.conn, scope, query_embedding.len(), SIMILARITY_CANDIDATES)?;
+        let best = candidates
+            .into_iter()
+            .filter_map(|(id, embedding)| {
+                let similarity = cosine_similarity(&embedding, query_embedding);
+                (similarity > threshold).then_some((similarity, id))
             })
-            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
-            .map(|(_, answer)| answer)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+
+        match best {
+            Some((_, id)) => Ok(Some(db::load_cached_answer(&self.conn, id)?)),
+            None => Ok(None),
+        }
     }
 }
 
@@ -155,7 +275,7 @@ pub fn hash_query_context(query: &str, context_chunks: &[String]) -> String {
     hex::encode(hasher.finalize())
 }
 
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     let dot = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>();
     let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
     let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
@@ -169,31 +289,59 @@ pub struct SessionState {
     pub responses: Vec<String>,
     pub created_at: u64,
     pub last_updated: u64,
+    /// Rolling summary of the Q&A pairs too old to send verbatim.
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// How many of the first Q&A pairs `summary` covers.
+    #[serde(default)]
+    pub summarized: usize,
 }
 
+/// Sessions stored in `state.db`.
+///
+/// Change sessions through the methods below so `save` knows which ones to
+/// write; it leaves the rest of the table alone.
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct SessionManager {
     pub sessions: HashMap<String, SessionState>,
     pub active_session: Option<String>,
+    #[serde(skip)]
+    dirty: HashSet<String>,
+    #[serde(skip)]
+    removed: HashSet<String>,
+    #[serde(skip)]
+    active_changed: bool,
 }
 
 impl SessionManager {
-    pub fn load(config_dir: &PathBuf) -> Result<Self> {
-        let path = config_dir.join("sessions.json");
-        if !path.exists() {
-            return Ok(SessionManager::default());
-        }
-        let contents = fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read sessions from {}", path.display()))?;
-        let state = serde_json::from_str(&contents)?;
-        Ok(state)
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let conn = db::open(config_dir)?;
+        db::load_sessions(&conn)
     }
 
-    pub fn save(&self, config_dir: &PathBuf) -> Result<()> {
-        let path = config_dir.join("sessions.json");
-        let json = serde_json::to_string_pretty(self)?;
-        let mut file = fs::File::create(&path)?;
-        file.write_all(json.as_bytes())?;
+    /// Writes the sessions changed since the last load or save, appending
+    /// their new turns, and the active session if it changed.
+    pub fn save(&mut self, config_dir: &Path) -> Result<()> {
+        if self.dirty.is_empty() && self.removed.is_empty() && !self.active_changed {
+            return Ok(());
+        }
+
+        let mut conn = db::open(config_dir)?;
+        let tx = conn.transaction()?;
+        for id in &self.removed {
+            db::delete_session(&tx, id)?;
+        }
+        for session in self.dirty.iter().filter_map(|id| self.sessions.get(id)) {
+            db::save_session(&tx, session)?;
+        }
+        if self.active_changed {
+            db::save_active_session(&tx, self.active_session.as_deref())?;
+        }
+        tx.commit()?;
+
+        self.dirty.clear();
+        self.removed.clear();
+        self.active_changed = false;
         Ok(())
     }
 
@@ -210,10 +358,14 @@ impl SessionManager {
             responses: Vec::new(),
             created_at: now,
             last_updated: now,
+            summary: None,
+            summarized: 0,
         };
 
         self.sessions.insert(session_id.clone(), session);
+        self.dirty.insert(session_id.clone());
         self.active_session = Some(session_id.clone());
+        self.active_changed = true;
         session_id
     }
 
@@ -223,6 +375,7 @@ impl SessionManager {
 
     pub fn get_active_session_mut(&mut self) -> Option<&mut SessionState> {
         if let Some(id) = &self.active_session {
+            self.dirty.insert(id.clone());
             self.sessions.get_mut(id)
         } else {
             None
@@ -248,12 +401,25 @@ impl SessionManager {
     pub fn set_active_session(&mut self, session_id: &str) -> Result<()> {
         if self.sessions.contains_key(session_id) {
             self.active_session = Some(session_id.to_string());
+            self.active_changed = true;
             Ok(())
         } else {
             anyhow::bail!("Session not found: {}", session_id)
         }
     }
 
+    /// Deletes a session, clearing the active session if it was the one removed.
+    pub fn remove_session(&mut self, session_id: &str) -> Option<SessionState> {
+        let session = self.sessions.remove(session_id)?;
+        self.dirty.remove(session_id);
+        self.removed.insert(session_id.to_string());
+        if self.active_session.as_deref() == Some(session_id) {
+            self.active_session = None;
+            self.active_changed = true;
+        }
+        Some(session)
+    }
+
     pub fn list_sessions(&self) -> Vec<(&String, &SessionState)> {
         self.sessions.iter().collect()
     }
@@ -0,0 +1,348 @@
+use super::{MetadataFilter, QueryHit, VectorRecord, VectorStore};
+use crate::embedding::EmbeddingInfo;
+use crate::state::cosine_similarity;
+use crate::utils;
+use anyhow::Context;
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const STORE_FILE: &str = "store.bin";
+const COLLECTION_FILE: &str = "collection.json";
+/// Layout before records and embeddings shared one file, still read by `open`.
+const LEGACY_RECORDS_FILE: &str = "records.json";
+const LEGACY_EMBEDDINGS_FILE: &str = "embeddings.bin";
+
+/// File-backed vector store doing exact cosine search in memory.
+///
+/// Everything lives in `store.bin`, replaced atomically on each save: a
+/// little-endian `u64` length and the records' documents and metadata as
+/// JSON, then a `u32` dimension followed by raw `f32`s in record order.
+/// `collection.json` records the embedding model.
+pub struct LocalStore {
+    dir: PathBuf,
+    collection: Mutex<Collection>,
+}
+
+#[derive(Default)]
+struct Collection {
+    records: Vec<VectorRecord>,
+    dirty: bool,
+}
+
+impl LocalStore {
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        let store_path = dir.join(STORE_FILE);
+        let legacy_path = dir.join(LEGACY_RECORDS_FILE);
+
+        let records = if store_path.exists() {
+            let bytes = fs::read(&store_path)
+                .with_context(|| format!("Failed to read vector store at {}", store_path.display()))?;
+            let json_len = bytes
+                .get(..8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+                .unwrap_or_default();
+            let Some(json) = bytes.get(8..8 + json_len) else {
+                anyhow::bail!("Vector store at {} is corrupt: it ends inside the records", dir.display());
+            };
+            read_records(dir, json, &bytes[8 + json_len..])?
+        } else if legacy_path.exists() {
+            let json = fs::read(&legacy_path)
+                .with_context(|| format!("Failed to read vector store at {}", legacy_path.display()))?;
+            read_records(dir, &json, &fs::read(dir.join(LEGACY_EMBEDDINGS_FILE))?)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(LocalStore {
+            dir: dir.to_path_buf(),
+            collection: Mutex::new(Collection { records, dirty: false }),
+        })
+    }
+
+    fn save(&self, collection: &Collection) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let json = serde_json::to_vec(&collection.records)?;
+        let dimension = collection.records.first().map_or(0, |r| r.embedding.len());
+        let mut bytes = Vec::with_capacity(12 + json.len() + dimension * collection.records.len() * 4);
+        bytes.extend_from_slice(&(json.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(&(dimension as u32).to_le_bytes());
+        for record in &collection.records {
+            for value in &record.embedding {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        // One rename replaces records and embeddings together, so a crash can't
+        // pair the old records with the new vectors. The legacy files are only
+        // removed once store.bin, which `open` prefers, is in place
+        utils::write_atomic(&self.dir.join(STORE_FILE), &bytes)?;
+        for file in [LEGACY_RECORDS_FILE, LEGACY_EMBEDDINGS_FILE] {
+            let path = self.dir.join(file);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the records' JSON and attaches the embeddings stored after it.
+fn read_records(dir: &Path, json: &[u8], embeddings: &[u8]) -> anyhow::Result<Vec<VectorRecord>> {
+    let mut records: Vec<VectorRecord> = serde_json::from_slice(json)?;
+
+    let dimension = embeddings
+        .get(..4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .unwrap_or_default();
+    let floats: Vec<f32> = embeddings
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+
+    if (dimension == 0 && !records.is_empty()) || floats.len() != dimension * records.len() {
+        anyhow::bail!("Vector store at {} is corrupt: embeddings don't match records", dir.display());
+    }
+    for (record, embedding) in records.iter_mut().zip(floats.chunks_exact(dimension.max(1))) {
+        record.embedding = embedding.to_vec();
+    }
+    Ok(records)
+}
+
+#[async_trait]
+impl VectorStore for LocalStore {
+    async fn ensure_collection(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        if self.collection.lock().unwrap().records.is_empty() && !self.dir.join(COLLECTION_FILE).exists() {
+            self.record_embedding(embedding).await?;
+        }
+        Ok(())
+    }
+
+    async fn embedding_info(&self) -> anyhow::Result<Option<EmbeddingInfo>> {
+        let path = self.dir.join(COLLECTION_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Some(serde_json::from_str(&contents)?))
+    }
+
+    async fn record_embedding(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        utils::write_atomic(&self.dir.join(COLLECTION_FILE), serde_json::to_string(embedding)?.as_bytes())
+    }
+
+    async fn delete_collection(&self) -> anyhow::Result<()> {
+        let mut collection = self.collection.lock().unwrap();
+        collection.records.clear();
+        collection.dirty = false;
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    async fn upsert(&self, records: &[VectorRecord]) -> anyhow::Result<()> {
+        let mut collection = self.collection.lock().unwrap();
+
+        if let Some(expected) = collection.records.first().map(|r| r.embedding.len()) {
+            if let Some(bad) = records.iter().find(|r| r.embedding.len() != expected) {
+                anyhow::bail!(
+                    "Embedding dimension {} doesn't match the collection's dimension {}",
+                    bad.embedding.len(),
+                    expected
+                );
+            }
+        }
+
+        let mut positions: HashMap<String, usize> = collection
+            .records
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.id.clone(), i))
+            .collect();
+        for record in records {
+            match positions.get(&record.id) {
+                Some(&i) => collection.records[i] = record.clone(),
+                None => {
+                    positions.insert(record.id.clone(), collection.records.len());
+                    collection.records.push(record.clone());
+                }
+            }
+        }
+        collection.dirty = true;
+        Ok(())
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<(String, serde_json::Value)>> {
+        let collection = self.collection.lock().unwrap();
+        Ok(collection.records.iter().map(|r| (r.id.clone(), r.metadata.clone())).collect())
+    }
+
+    async fn query(&self, embedding: &[f32], top_k: usize, filter: &MetadataFilter) -> anyhow::Result<Vec<QueryHit>> {
+        let collection = self.collection.lock().unwrap();
+
+        let mut scored: Vec<(f64, &VectorRecord)> = collection
+            .records
+            .iter()
+            .filter(|r| r.embedding.len() == embedding.len() && filter.matches_metadata(&r.metadata))
+            .map(|r| (1.0 - cosine_similarity(&r.embedding, embedding) as f64, r))
+            .collect();
+
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+        scored.truncate(top_k);
+
+        Ok(scored
+            .into_iter()
+            .map(|(distance, r)| QueryHit {
+                id: r.id.clone(),
+                document: r.document.clone(),
+                metadata: r.metadata.clone(),
+                distance: Some(distance),
+                score: None,
+            })
+            .collect())
+    }
+
+    async fn delete(&self, ids: &[String]) -> anyhow::Result<()> {
+        let mut collection = self.collection.lock().unwrap();
+        let before = collection.records.len();
+        let ids: HashSet<&String> = ids.iter().collect();
+        collection.records.retain(|r| !ids.contains(&r.id));
+        collection.dirty |= collection.records.len() != before;
+        Ok(())
+    }
+
+    async fn update_metadata(&self, ids: &[String], metadatas: &[serde_json::Value]) -> anyhow::Result<()> {
+        let mut collection = self.collection.lock().unwrap();
+        let updates: HashMap<&String, &serde_json::Value> = ids.iter().zip(metadatas).collect();
+        for record in collection.records.iter_mut() {
+            if let Some(metadata) = updates.get(&record.id) {
+                record.metadata = (*metadata).clone();
+            }
+        }
+        collection.dirty |= !updates.is_empty();
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        let mut collection = self.collection.lock().unwrap();
+        if collection.dirty {
+            self.save(&collection)?;
+            collection.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedding::{EmbeddingProvider, MockEmbedder};
+    use uuid::Uuid;
+
+    const DOCUMENTS: [(&str, &str); 3] = [
+        ("src/auth.rs", "fn login(user: &str, password: &str) -> Session"),
+        ("src/db.rs", "fn open_database(path: &Path) -> Connection"),
+        ("docs/auth.md", "Users log in with a password and get a session token"),
+    ];
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("kb-local-store-{}", Uuid::new_v4()))
+    }
+
+    async fn records(embedder: &MockEmbedder) -> Vec<VectorRecord> {
+        let texts: Vec<String> = DOCUMENTS.iter().map(|(_, text)| text.to_string()).collect();
+        let embeddings = embedder.embed(&texts).await.unwrap();
+        DOCUMENTS
+            .iter()
+            .zip(embeddings)
+            .enumerate()
+            .map(|(i, ((source, text), embedding))| VectorRecord {
+                id: format!("chunk-{}", i),
+                document: text.to_string(),
+                embedding,
+                metadata: serde_json::json!({ "source": source }),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn finds_the_nearest_chunks_after_reopening() {
+        let dir = temp_dir();
+        let embedder = MockEmbedder::new(64);
+        let store = LocalStore::open(&dir).unwrap();
+        store.upsert(&records(&embedder).await).await.unwrap();
+        store.flush().await.unwrap();
+
+        let store = LocalStore::open(&dir).unwrap();
+        let query = embedder.embed_one("open_database path").await.unwrap();
+        let hits = store.query(&query, 2, &MetadataFilter::default()).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].id, "chunk-1");
+        assert!(hits[0].distance.unwrap() < hits[1].distance.unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn filters_and_deletes_chunks() {
+        let dir = temp_dir();
+        let embedder = MockEmbedder::new(64);
+        let store = LocalStore::open(&dir).unwrap();
+        store.upsert(&records(&embedder).await).await.unwrap();
+
+        let query = embedder.embed_one("password session").await.unwrap();
+        let filter = MetadataFilter { languages: vec!["markdown".to_string()], ..Default::default() };
+        let hits = store.query(&query, 3, &filter).await.unwrap();
+        assert_eq!(hits.iter().map(|h| h.id.as_str()).collect::<Vec<_>>(), ["chunk-2"]);
+
+        store.delete(&["chunk-0".to_string()]).await.unwrap();
+        store.flush().await.unwrap();
+        let ids: Vec<String> = LocalStore::open(&dir).unwrap().list().await.unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, ["chunk-1", "chunk-2"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrates_the_two_file_layout() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let records = records(&MockEmbedder::new(4)).await;
+        let mut embeddings = 4u32.to_le_bytes().to_vec();
+        for value in records.iter().flat_map(|r| &r.embedding) {
+            embeddings.extend_from_slice(&value.to_le_bytes());
+        }
+        fs::write(dir.join(LEGACY_EMBEDDINGS_FILE), embeddings).unwrap();
+        fs::write(dir.join(LEGACY_RECORDS_FILE), serde_json::to_vec(&records).unwrap()).unwrap();
+
+        let store = LocalStore::open(&dir).unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 3);
+        store.delete(&["chunk-0".to_string()]).await.unwrap();
+        store.flush().await.unwrap();
+        assert!(dir.join(STORE_FILE).exists() && !dir.join(LEGACY_RECORDS_FILE).exists());
+
+        let store = LocalStore::open(&dir).unwrap();
+        let hits = store.query(&records[1].embedding, 1, &MetadataFilter::default()).await.unwrap();
+        assert_eq!(hits[0].id, "chunk-1");
+        assert!(hits[0].distance.unwrap() < 1e-6);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_embeddings_of_another_dimension() {
+        let store = LocalStore::open(&temp_dir()).unwrap();
+        store.upsert(&records(&MockEmbedder::new(64)).await).await.unwrap();
+        assert!(store.upsert(&records(&MockEmbedder::new(32)).await).await.is_err());
+    }
+}
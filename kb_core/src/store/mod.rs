@@ -0,0 +1,213 @@
+pub mod local;
+
+use crate::chroma::ChromaClient;
+use crate::chunking::{self, Chunk};
+use crate::config::{self, AppConfig};
+use crate::embedding::EmbeddingInfo;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub use local::LocalStore;
+
+/// A chunk as written to a vector store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorRecord {
+    pub id: String,
+    pub document: String,
+    #[serde(skip)]
+    pub embedding: Vec<f32>,
+    pub metadata: serde_json::Value,
+}
+
+/// A chunk returned by a search, best match first.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryHit {
+    pub id: String,
+    pub document: String,
+    pub metadata: serde_json::Value,
+    /// Vector distance; `None` for chunks found only by the lexical search.
+    pub distance: Option<f64>,
+    /// BM25 or fused rank score, higher is better; `None` for plain vector search.
+    pub score: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult<'a> {
+    pub index: usize,
+    pub source: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breadcrumb: Option<&'a str>,
+    pub start_line: Option<u64>,
+    pub end_line: Option<u64>,
+    /// `path:start-end`, or just the path for chunks indexed without line ranges.
+    pub location: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    pub content: &'a str,
+}
+
+impl<'a> SearchResult<'a> {
+    pub fn from_hit(index: usize, hit: &'a QueryHit) -> Self {
+        let meta = &hit.metadata;
+        let source = meta.get("source").and_then(|v| v.as_str()).unwrap_or("<unknown>");
+        let start_line = meta.get("start_line").and_then(|v| v.as_u64());
+        let end_line = meta.get("end_line").and_then(|v| v.as_u64());
+
+        SearchResult {
+            index,
+            source,
+            breadcrumb: meta.get("breadcrumb").and_then(|v| v.as_str()),
+            start_line,
+            end_line,
+            location: chunk_location(source, start_line, end_line),
+            distance: hit.distance,
+            score: hit.score,
+            content: &hit.document,
+        }
+    }
+}
+
+/// Conditions on chunk metadata that every query result must meet.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataFilter {
+    /// Only chunks from these files, when set.
+    pub sources: Option<Vec<String>>,
+    /// Never chunks from these files.
+    pub excluded_sources: Vec<String>,
+    /// Only chunks in one of these languages, when non-empty.
+    pub languages: Vec<String>,
+    /// Only chunks whose file was modified at or after this Unix time.
+    pub modified_since: Option<u64>,
+}
+
+impl MetadataFilter {
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_none()
+            && self.excluded_sources.is_empty()
+            && self.languages.is_empty()
+            && self.modified_since.is_none()
+    }
+
+    /// Whether a chunk with the given source, language and modification time passes.
+    /// Missing values fail any condition on them.
+    pub fn matches(&self, source: &str, language: Option<&str>, modified: Option<u64>) -> bool {
+        if let Some(sources) = &self.sources {
+            if !sources.iter().any(|s| s == source) {
+                return false;
+            }
+        }
+        if self.excluded_sources.iter().any(|s| s == source) {
+            return false;
+        }
+        if !self.languages.is_empty() && !language.is_some_and(|l| self.languages.iter().any(|s| s == l)) {
+            return false;
+        }
+        match self.modified_since {
+            Some(since) => modified.is_some_and(|m| m >= since),
+            None => true,
+        }
+    }
+
+    /// Like `matches`, taking the language from the file extension for chunks
+    /// indexed before it was recorded.
+    pub fn matches_metadata(&self, metadata: &serde_json::Value) -> bool {
+        let source = metadata.get("source").and_then(|v| v.as_str()).unwrap_or_default();
+        let language = match metadata.get("language").and_then(|v| v.as_str()) {
+            Some(language) => Some(language.to_string()),
+            None => Path::new(source).extension().and_then(|e| e.to_str()).map(chunking::language_name),
+        };
+        self.matches(source, language.as_deref(), metadata.get("modified").and_then(|v| v.as_u64()))
+    }
+}
+
+pub fn chunk_location(source: &str, start_line: Option<u64>, end_line: Option<u64>) -> String {
+    match (start_line, end_line) {
+        (Some(start), Some(end)) => format!("{}:{}-{}", source, start, end),
+        _ => source.to_string(),
+    }
+}
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Creates the collection if it doesn't exist yet, recording the model
+    /// its vectors come from. Existing collections are left as they are.
+    async fn ensure_collection(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()>;
+
+    /// The embedding model recorded for the collection, if any.
+    async fn embedding_info(&self) -> anyhow::Result<Option<EmbeddingInfo>>;
+
+    /// Records the embedding model of a collection created before models were tracked.
+    async fn record_embedding(&self, embedding: &EmbeddingInfo) -> anyhow::Result<()>;
+
+    /// Removes the collection and every vector in it.
+    async fn delete_collection(&self) -> anyhow::Result<()>;
+
+    /// Inserts records, replacing any that already exist with the same ID.
+    async fn upsert(&self, records: &[VectorRecord]) -> anyhow::Result<()>;
+
+    /// Returns the ID and metadata of every record.
+    async fn list(&self) -> anyhow::Result<Vec<(String, serde_json::Value)>>;
+
+    /// Returns the `top_k` records nearest to `embedding` that pass `filter`.
+    async fn query(&self, embedding: &[f32], top_k: usize, filter: &MetadataFilter) -> anyhow::Result<Vec<QueryHit>>;
+
+    async fn delete(&self, ids: &[String]) -> anyhow::Result<()>;
+
+    /// Replaces the metadata of existing records, e.g. after their file was renamed.
+    async fn update_metadata(&self, ids: &[String], metadatas: &[serde_json::Value]) -> anyhow::Result<()>;
+
+    /// Persists pending writes. Stores writing through to a server have nothing to do.
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Fails if the store's vectors came from a different model than `embedding`.
+/// Collections that predate model tracking are assumed to match and get it recorded.
+pub async fn check_embedding(store: &dyn VectorStore, embedding: &EmbeddingInfo) -> anyhow::Result<()> {
+    match store.embedding_info().await? {
+        Some(recorded) => recorded.check(embedding),
+        None => store.record_embedding(embedding).await,
+    }
+}
+
+/// Opens `collection` in the vector store selected by `vector_store` in the config.
+pub fn from_config(config: &AppConfig, collection: &str) -> anyhow::Result<Box<dyn VectorStore>> {
+    let store: Box<dyn VectorStore> = match config.vector_store() {
+        "chroma" => Box::new(ChromaClient::new(config, collection)?),
+        "local" => {
+            let dir = config::get_config_dir()?.join("vectors").join(collection);
+            Box::new(LocalStore::open(&dir)?)
+        }
+        other => anyhow::bail!("Unknown vector store '{}'. Expected one of: chroma, local", other),
+    };
+
+    Ok(store)
+}
+
+/// Metadata stored alongside each chunk. The hash lets the index state be
+/// rebuilt from the store; extension, language and modification time are
+/// there for query filters.
+/// `source` is the file's path as `Project::index_path` records it.
+pub fn chunk_metadata(chunk: &Chunk, source: &str, hash: &str, modified: u64) -> serde_json::Value {
+    let extension = Path::new(source).extension().and_then(|s| s.to_str()).unwrap_or_default();
+    let mut metadata = serde_json::json!({
+        "source": source,
+        "hash": hash,
+        "extension": extension,
+        "language": chunking::language_name(extension),
+        "modified": modified,
+        "start_line": chunk.start_line,
+        "end_line": chunk.end_line,
+        "start_byte": chunk.start_byte,
+        "end_byte": chunk.end_byte,
+    });
+    if let Some(breadcrumb) = &chunk.breadcrumb {
+        metadata["breadcrumb"] = serde_json::json!(breadcrumb);
+    }
+
+    metadata
+}
@@ -6,6 +6,7 @@ use syntect::parsing::SyntaxReference;
 use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
 use std::path::{Path, PathBuf};
 use std::collections::HashSet;
+use std::io::Write;
 
 pub fn highlight_syntax(code: &str, file_path: &str) -> String {
     let config = config::load_config().expect("failed to load config");
@@ -85,15 +86,6 @@ pub fn collect_files(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-pub fn chunk_text(text: &str) -> Vec<String> {
-    text.lines()
-        .collect::<Vec<_>>()
-        .chunks(10)
-        .map(|chunk| chunk.join("\n"))
-        .filter(|chunk| !chunk.trim().is_empty())
-        .collect()
-}
-
 use regex::Regex;
 
 use crate::config;
@@ -117,3 +109,111 @@ pub fn render_markdown_highlighted(md: &str) -> String {
     out.push_str(&md[last..]);
     out
 }
+
+/// Renders a Markdown answer as it streams in.
+///
+/// Prose is passed through as soon as it can't be the start of a code fence.
+/// Code blocks are held back and highlighted with [`render_markdown_highlighted`]
+/// once their closing fence arrives.
+#[derive(Default)]
+pub struct MarkdownStream {
+    /// The current, unfinished line.
+    line: String,
+    /// How much of `line` has already been returned.
+    emitted: usize,
+    /// The open code block, fences included, while inside one.
+    code: Option<String>,
+}
+
+impl MarkdownStream {
+    /// Adds a piece of the answer, returning the text that can be printed now.
+    pub fn push(&mut self, token: &str) -> String {
+        let mut out = String::new();
+        for piece in token.split_inclusive('\n') {
+            self.line.push_str(piece);
+            if self.line.ends_with('\n') {
+                let line = std::mem::take(&mut self.line);
+                let emitted = std::mem::take(&mut self.emitted);
+                self.end_line(&line, emitted, &mut out);
+            }
+        }
+
+        if self.code.is_none() && !may_open_fence(&self.line) {
+            out.push_str(&self.line[self.emitted..]);
+            self.emitted = self.line.len();
+        }
+        out
+    }
+
+    fn end_line(&mut self, line: &str, emitted: usize, out: &mut String) {
+        let fence = line.trim_start().starts_with("```");
+        match &mut self.code {
+            Some(code) => {
+                code.push_str(line);
+                if fence {
+                    out.push_str(&render_markdown_highlighted(code));
+                    self.code = None;
+                }
+            }
+            None if fence => self.code = Some(line.to_string()),
+            None => out.push_str(&line[emitted..]),
+        }
+    }
+
+    /// Returns what's left once the answer is complete, such as an unclosed code block.
+    pub fn finish(mut self) -> String {
+        let mut out = self.code.take().unwrap_or_default();
+        out.push_str(&self.line[self.emitted..]);
+        out
+    }
+}
+
+/// Whether an unfinished line is, or could still become, a code fence.
+fn may_open_fence(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("```") || "```".starts_with(trimmed)
+}
+
+/// Writes a file by renaming a fully written temporary file over it, so a crash
+/// or Ctrl-C leaves either the old contents or the new ones.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid path {}", path.display()))?;
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Rough token count for budgeting requests (about four characters per token).
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Length of the longest prefix of `text` within `max_tokens` estimated tokens,
+/// ending on a character boundary.
+pub fn truncated_len(text: &str, max_tokens: usize) -> usize {
+    let mut len = text.len().min(max_tokens * 4);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_len_keeps_short_text_and_cuts_on_char_boundaries() {
+        assert_eq!(truncated_len("short", 10), 5);
+        assert_eq!(truncated_len("abcdefghij", 2), 8);
+        // 'é' spans bytes 7 and 8, so a cut at 8 backs up to 7
+        assert_eq!(truncated_len("abcdefgé", 2), 7);
+    }
+}
@@ -1,7 +1,7 @@
 use kb_core::cli;
 use kb_core::config;
 
-use cli::{commands, Cli};
+use cli::{commands, Cli, ProjectsCommand};
 use clap::Parser;
 use reqwest::Client;
 
@@ -15,8 +15,10 @@ async fn main() -> anyhow::Result<()> {
             // Config command doesn't need the API key validation
             return commands::config::handle_config(set_api_key, show);
         }
+        Cli::Sessions { .. } | Cli::Projects(_) => {}
+        _ if !needs_openai_key(&cli)? => {}
         _ => {
-            // For other commands, validate that we have an OpenAI API key before proceeding
+            // For commands using OpenAI, validate that we have an API key before proceeding
             match config::get_openai_api_key() {
                 Ok(_) => {}, // Key exists, continue
                 Err(e) => {
@@ -43,17 +45,41 @@ async fn main() -> anyhow::Result<()> {
     }
 
     match cli {
-        Cli::Index { path } => {
-            commands::index::handle_index(&client, &path).await?;
+        Cli::Index { path, watch, project } => {
+            commands::index::handle_index(&client, &path, watch, project).await?;
         }
-        Cli::Query { query, top_k, format, session} => {
-            commands::query::handle_query(&client, &query, top_k, &format, session).await?;
+        Cli::Reindex { model, project } => {
+            commands::index::handle_reindex(&client, &model, project).await?;
         }
-        Cli::Sessions { list, clear, switch } => {
-            commands::session::handle_sessions(list, clear, switch)?;
+        Cli::Query { query, top_k, format, mode, session, project, filters } => {
+            commands::query::handle_query(&client, &query, top_k, &format, &mode, session, project, &filters).await?;
+        }
+        Cli::Chat { top_k, format, mode, project, filters } => {
+            commands::chat::handle_chat(&client, top_k, &format, &mode, project, &filters).await?;
+        }
+        Cli::Browse { query, top_k, mode, project, filters } => {
+            commands::browse::handle_browse(&client, &query, top_k, &mode, project, &filters).await?;
+        }
+        Cli::Sessions { list, clear, switch, project } => {
+            commands::session::handle_sessions(list, clear, switch, project)?;
+        }
+        Cli::Projects(ProjectsCommand::List) => {
+            commands::projects::handle_list()?;
+        }
+        Cli::Projects(ProjectsCommand::Remove { name }) => {
+            commands::projects::handle_remove(&name).await?;
         }
         _ => {} // Config case already handled above
     }
 
     Ok(())
 }
+
+fn needs_openai_key(cli: &Cli) -> anyhow::Result<bool> {
+    let app_config = config::load_config()?;
+    let uses_openai_llm = app_config.llm_provider() == "openai"
+        && matches!(cli, Cli::Query { format, .. } | Cli::Chat { format, .. } if format == "smart");
+    let uses_openai_embeddings = app_config.embedding_provider() == "openai"
+        && !matches!(cli, Cli::Query { mode, .. } | Cli::Chat { mode, .. } | Cli::Browse { mode, .. } if mode == "lexical");
+    Ok(uses_openai_embeddings || uses_openai_llm)
+}